@@ -0,0 +1,45 @@
+/// Embeds the SQL files under `migrations/` so the binary can apply them without diesel_cli
+
+use std::{env, fs, path::Path};
+
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let migrations_dir = Path::new(&manifest_dir).join("migrations");
+
+    let mut directories = fs::read_dir(&migrations_dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+
+    directories.sort();
+
+    let mut code = String::from("pub static MIGRATIONS: &[Migration] = &[\n");
+
+    for directory in directories {
+        let name = directory.file_name().unwrap().to_str().unwrap();
+        // Same versioning as diesel: `2020-03-14-055636_create_db` -> `20200314055636`
+        let version = name.split('_').next().unwrap().replace('-', "");
+        let up = directory.join("up.sql");
+        let down = directory.join("down.sql");
+
+        println!("cargo:rerun-if-changed={}", up.to_str().unwrap());
+        println!("cargo:rerun-if-changed={}", down.to_str().unwrap());
+
+        code.push_str(&format!(
+            "    Migration {{ version: {:?}, name: {:?}, up: include_str!({:?}), down: include_str!({:?}) }},\n",
+            version,
+            name,
+            up.to_str().unwrap(),
+            down.to_str().unwrap()
+        ));
+    }
+
+    code.push_str("];\n");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("migrations.rs"), code).unwrap();
+}
@@ -0,0 +1,3 @@
+drop index if exists posts_author_id_created_at_id_idx;
+drop index if exists posts_created_at_id_idx;
+drop index if exists users_created_at_id_idx;
@@ -0,0 +1,3 @@
+create index users_created_at_id_idx on users (created_at, id);
+create index posts_created_at_id_idx on posts (created_at, id);
+create index posts_author_id_created_at_id_idx on posts (author_id, created_at, id);
@@ -0,0 +1,7 @@
+drop index if exists posts_status_published_at_idx;
+
+alter table posts
+    drop column published_at,
+    drop column status;
+
+drop type if exists post_status;
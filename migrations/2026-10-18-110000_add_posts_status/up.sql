@@ -0,0 +1,12 @@
+create type post_status as enum ('draft', 'published', 'scheduled');
+
+-- Existing posts were already public
+alter table posts
+    add column status post_status not null default 'published',
+    add column published_at timestamp null;
+
+update posts set published_at = created_at;
+
+alter table posts alter column status set default 'draft';
+
+create index posts_status_published_at_idx on posts (status, published_at);
@@ -0,0 +1,15 @@
+create table tags (
+    id uuid default uuid_generate_v4() primary key,
+    name varchar not null unique,
+    created_at timestamp not null default current_timestamp
+);
+
+create table posts_tags (
+    post_id uuid not null,
+    tag_id uuid not null,
+    primary key (post_id, tag_id),
+    foreign key (post_id) references posts(id) on delete cascade,
+    foreign key (tag_id) references tags(id) on delete cascade
+);
+
+create index posts_tags_tag_id_idx on posts_tags (tag_id);
@@ -0,0 +1,7 @@
+alter table posts add column search tsvector generated always as (
+    setweight(to_tsvector('english', title), 'A') ||
+    setweight(to_tsvector('english', description), 'B') ||
+    setweight(to_tsvector('english', body), 'C')
+) stored;
+
+create index posts_search_idx on posts using gin (search);
@@ -0,0 +1,15 @@
+alter table tags
+    alter column created_at type timestamp using created_at at time zone 'UTC';
+
+alter table comments
+    alter column created_at type timestamp using created_at at time zone 'UTC',
+    alter column updated_at type timestamp using updated_at at time zone 'UTC';
+
+alter table posts
+    alter column created_at type timestamp using created_at at time zone 'UTC',
+    alter column updated_at type timestamp using updated_at at time zone 'UTC',
+    alter column published_at type timestamp using published_at at time zone 'UTC';
+
+alter table users
+    alter column created_at type timestamp using created_at at time zone 'UTC',
+    alter column updated_at type timestamp using updated_at at time zone 'UTC';
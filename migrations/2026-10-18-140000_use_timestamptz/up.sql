@@ -0,0 +1,16 @@
+-- Existing values were written in UTC
+alter table users
+    alter column created_at type timestamptz using created_at at time zone 'UTC',
+    alter column updated_at type timestamptz using updated_at at time zone 'UTC';
+
+alter table posts
+    alter column created_at type timestamptz using created_at at time zone 'UTC',
+    alter column updated_at type timestamptz using updated_at at time zone 'UTC',
+    alter column published_at type timestamptz using published_at at time zone 'UTC';
+
+alter table comments
+    alter column created_at type timestamptz using created_at at time zone 'UTC',
+    alter column updated_at type timestamptz using updated_at at time zone 'UTC';
+
+alter table tags
+    alter column created_at type timestamptz using created_at at time zone 'UTC';
@@ -0,0 +1,4 @@
+drop trigger comments_set_updated_at on comments;
+drop trigger posts_set_updated_at on posts;
+drop trigger users_set_updated_at on users;
+drop function set_updated_at();
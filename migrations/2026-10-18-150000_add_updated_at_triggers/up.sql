@@ -0,0 +1,15 @@
+create or replace function set_updated_at() returns trigger as $$
+begin
+    new.updated_at = current_timestamp;
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger users_set_updated_at before update on users
+    for each row execute procedure set_updated_at();
+
+create trigger posts_set_updated_at before update on posts
+    for each row execute procedure set_updated_at();
+
+create trigger comments_set_updated_at before update on comments
+    for each row execute procedure set_updated_at();
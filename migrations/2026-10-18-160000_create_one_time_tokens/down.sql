@@ -0,0 +1,5 @@
+alter table users drop column password_changed_at;
+
+drop table one_time_tokens;
+
+drop type token_purpose;
@@ -0,0 +1,17 @@
+create type token_purpose as enum ('password_reset', 'email_verification');
+
+create table one_time_tokens (
+    id uuid default uuid_generate_v4() primary key,
+    user_id uuid not null references users(id) on delete cascade,
+    purpose token_purpose not null,
+    -- SHA-256 of the token sent by email
+    token_hash varchar not null unique,
+    expires_at timestamptz not null,
+    used_at timestamptz null,
+    created_at timestamptz not null default current_timestamp
+);
+
+create index one_time_tokens_user_id_purpose_idx on one_time_tokens (user_id, purpose);
+
+-- Access tokens issued before the last password change are rejected
+alter table users add column password_changed_at timestamptz null;
@@ -0,0 +1,4 @@
+alter table users add column email_verified_at timestamptz null;
+
+-- Accounts created before verification existed are trusted
+update users set email_verified_at = created_at;
@@ -0,0 +1,7 @@
+alter table users add column is_admin boolean not null default false;
+
+update users set is_admin = true where role = 'admin';
+
+alter table users drop column role;
+
+drop type user_role;
@@ -0,0 +1,7 @@
+create type user_role as enum ('reader', 'author', 'editor', 'admin');
+
+alter table users add column role user_role not null default 'author';
+
+update users set role = 'admin' where is_admin;
+
+alter table users drop column is_admin;
@@ -1,28 +1,109 @@
 pub use config::ConfigError;
 use crate::errors::{AppError, AppErrorType};
+use crate::mailer::{FileMailer, Mailer, StdoutMailer};
+use crate::metrics::PASSWORD_HASH_DURATION;
+use crate::one_time_token::OneTimeToken;
 use deadpool_postgres::Pool;
 use dotenv::dotenv;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use slog::{o, Drain};
 use slog_async;
 use slog_envlogger;
 use slog_term;
 use tokio_postgres::NoTls;
-use argonautica::Hasher;
+use argonautica::{Hasher, Verifier};
 use futures::compat::Future01CompatExt;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use uuid::Uuid;
+use std::{path::PathBuf, sync::Arc};
 
 #[derive(Deserialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: i32,
     pub url: String,
-    pub secret_key: String
+    pub secret_key: String,
+    /// Include internal error causes in responses
+    #[serde(default)]
+    pub debug: bool
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Access token lifetime in seconds
+    pub token_ttl: i64,
+    /// Password reset token lifetime in seconds
+    pub password_reset_ttl: i64,
+    /// Email verification token lifetime in seconds
+    pub email_verification_ttl: i64,
+    /// Users must verify their email address before creating posts
+    pub require_verified_email: bool
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            token_ttl: 60 * 60 * 24,
+            password_reset_ttl: 60 * 60,
+            email_verification_ttl: 60 * 60 * 48,
+            require_verified_email: false
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    /// `stdout` or `file`, required so a deploy can't print live links to the logs by omission
+    pub transport: Option<String>,
+    /// Directory used by the `file` transport
+    pub dir: String
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: None,
+            dir: "mail".to_string()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SiteConfig {
+    pub title: String,
+    pub description: String
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig {
+            title: "actix-blog-app".to_string(),
+            description: "Blog made in actix".to_string()
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct MigrationsConfig {
+    /// Apply pending migrations before starting the server
+    pub run_on_startup: bool
 }
 
 #[derive(Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub pg: deadpool_postgres::Config,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub site: SiteConfig,
+    #[serde(default)]
+    pub migrations: MigrationsConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
 }
 
 impl Config {
@@ -44,8 +125,51 @@ impl Config {
     }
 
     pub fn hashing_service(&self) -> HashingService {
-        HashingService {
-            secret_key: self.server.secret_key.clone()
+        HashingService::new(self.server.secret_key.clone())
+    }
+
+    pub fn token_service(&self) -> TokenService {
+        TokenService {
+            secret_key: self.server.secret_key.clone(),
+            ttl: self.auth.token_ttl,
+            password_reset_ttl: self.auth.password_reset_ttl,
+            email_verification_ttl: self.auth.email_verification_ttl
+        }
+    }
+
+    pub fn auth_policy(&self) -> AuthPolicy {
+        AuthPolicy {
+            require_verified_email: self.auth.require_verified_email
+        }
+    }
+
+    pub fn error_reporting(&self) -> ErrorReporting {
+        ErrorReporting {
+            debug: self.server.debug
+        }
+    }
+
+    pub fn site(&self) -> Site {
+        Site {
+            url: self.server.url.trim_end_matches('/').to_string(),
+            title: self.site.title.clone(),
+            description: self.site.description.clone()
+        }
+    }
+
+    /// Missing or unknown transports are rejected rather than falling back to `stdout`,
+    /// which would print working reset links to the logs
+    pub fn mailer(&self) -> Result<Arc<dyn Mailer>, ConfigError> {
+        match self.mail.transport.as_deref() {
+            Some("stdout") => Ok(Arc::new(StdoutMailer)),
+            Some("file") => Ok(Arc::new(FileMailer { dir: PathBuf::from(&self.mail.dir) })),
+            Some(transport) => Err(ConfigError::Message(format!(
+                "Unknown mail transport `{}`, expected `stdout` or `file`",
+                transport
+            ))),
+            None => Err(ConfigError::Message(
+                "MAIL__TRANSPORT must be set to `stdout` or `file`".to_string()
+            ))
         }
     }
 
@@ -60,13 +184,66 @@ impl Config {
     }
 }
 
+/// Rules applied by the GraphQL resolvers on top of authentication
+#[derive(Clone)]
+pub struct AuthPolicy {
+    pub require_verified_email: bool
+}
+
+/// How errors are rendered in GraphQL responses
+#[derive(Clone)]
+pub struct ErrorReporting {
+    /// Include internal error causes
+    pub debug: bool
+}
+
+/// Public information about the blog, used to build absolute links
+#[derive(Clone)]
+pub struct Site {
+    pub url: String,
+    pub title: String,
+    pub description: String
+}
+
+impl Site {
+    pub fn post_url(&self, slug: &str) -> String {
+        format!("{}/posts/{}", self.url, slug)
+    }
+
+    pub fn user_url(&self, username: &str) -> String {
+        format!("{}/users/{}", self.url, username)
+    }
+
+    pub fn password_reset_url(&self, token: &str) -> String {
+        format!("{}/reset-password?token={}", self.url, token)
+    }
+
+    pub fn email_verification_url(&self, token: &str) -> String {
+        format!("{}/verify-email?token={}", self.url, token)
+    }
+}
+
 #[derive(Clone)]
 pub struct HashingService {
-    secret_key: String
+    secret_key: String,
+    /// Hash of a random password, with the same parameters as real ones
+    dummy_hash: String
 }
 
 impl HashingService {
+    pub fn new(secret_key: String) -> HashingService {
+        let dummy_hash = Hasher::default()
+            .with_password(OneTimeToken::generate().token)
+            .with_secret_key(&secret_key)
+            .hash()
+            .expect("Error hashing dummy password");
+
+        HashingService { secret_key, dummy_hash }
+    }
+
     pub async fn hash(&self, password: String) -> Result<String, AppError> { // ~300ms
+        let _timer = PASSWORD_HASH_DURATION.start_timer();
+
         Hasher::default()
             .with_password(&password)
             .with_secret_key(&self.secret_key)
@@ -77,8 +254,168 @@ impl HashingService {
                 AppError {
                     message: Some("Invalid password provided".to_string()),
                     cause: Some(err.to_string()),
-                    error_type: AppErrorType::InvalidField
+                    error_type: AppErrorType::InvalidField,
+                    field: Some("password".to_string())
+                }
+            })
+    }
+
+    /// Takes as long as `verify`, used when there is no user to check the password against
+    /// so response times don't reveal which accounts exist
+    pub async fn verify_dummy(&self, password: String) -> Result<(), AppError> {
+        self.verify(password, self.dummy_hash.clone()).await.map(|_| ())
+    }
+
+    pub async fn verify(&self, password: String, hash: String) -> Result<bool, AppError> { // ~300ms
+        Verifier::default()
+            .with_hash(&hash)
+            .with_password(&password)
+            .with_secret_key(&self.secret_key)
+            .verify_non_blocking()
+            .compat()
+            .await
+            .map_err(|err| {
+                AppError {
+                    message: Some("Unable to verify password".to_string()),
+                    cause: Some(err.to_string()),
+                    error_type: AppErrorType::InvalidField,
+                    field: None
                 }
             })
     }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: Uuid,
+    iat: i64,
+    exp: i64
+}
+
+/// Claims of a valid access token
+pub struct TokenClaims {
+    pub user_id: Uuid,
+    pub issued_at: DateTime<Utc>
+}
+
+pub struct AccessToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>
+}
+
+/// Issues and validates signed (HS256) access tokens
+#[derive(Clone)]
+pub struct TokenService {
+    secret_key: String,
+    ttl: i64,
+    password_reset_ttl: i64,
+    email_verification_ttl: i64
+}
+
+impl TokenService {
+    /// Single-use token to reset a password, with its expiration
+    pub fn password_reset_token(&self) -> (OneTimeToken, DateTime<Utc>) {
+        (OneTimeToken::generate(), Utc::now() + Duration::seconds(self.password_reset_ttl))
+    }
+
+    /// Single-use token to verify an email address, with its expiration
+    pub fn email_verification_token(&self) -> (OneTimeToken, DateTime<Utc>) {
+        (OneTimeToken::generate(), Utc::now() + Duration::seconds(self.email_verification_ttl))
+    }
+
+    pub fn generate(&self, user_id: Uuid) -> Result<AccessToken, AppError> {
+        let now = Utc::now();
+        let expires_at = now + Duration::seconds(self.ttl);
+
+        let claims = Claims {
+            sub: user_id,
+            iat: now.timestamp(),
+            exp: expires_at.timestamp()
+        };
+
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(self.secret_key.as_bytes()))
+            .map_err(|err| {
+                AppError {
+                    message: Some("Error generating access token".to_string()),
+                    cause: Some(err.to_string()),
+                    error_type: AppErrorType::InternalError,
+                    field: None
+                }
+            })?;
+
+        Ok(AccessToken {
+            token,
+            expires_at
+        })
+    }
+
+    /// Returns the user the token was issued for and when
+    pub fn verify(&self, token: &str) -> Result<TokenClaims, AppError> {
+        decode::<Claims>(token, &DecodingKey::from_secret(self.secret_key.as_bytes()), &Validation::default())
+            .map(|data| TokenClaims {
+                user_id: data.claims.sub,
+                issued_at: Utc.timestamp(data.claims.iat, 0)
+            })
+            .map_err(|err| {
+                AppError {
+                    message: Some("Invalid or expired access token".to_string()),
+                    cause: Some(err.to_string()),
+                    error_type: AppErrorType::Unauthorized,
+                    field: None
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::TokenService;
+    use uuid::Uuid;
+
+    fn token_service(ttl: i64) -> TokenService {
+        TokenService {
+            secret_key: "test-secret-key".to_string(),
+            ttl,
+            password_reset_ttl: 60,
+            email_verification_ttl: 60
+        }
+    }
+
+    #[test]
+    fn test_token_roundtrip() {
+        let service = token_service(60);
+        let user_id = Uuid::new_v4();
+
+        let token = service.generate(user_id).unwrap();
+
+        assert_eq!(
+            service.verify(&token.token).unwrap().user_id,
+            user_id,
+            "Token should resolve to the user it was issued for"
+        );
+    }
+
+    #[test]
+    fn test_expired_token() {
+        let service = token_service(-120);
+
+        let token = service.generate(Uuid::new_v4()).unwrap();
+
+        assert!(service.verify(&token.token).is_err(), "Expired token should be rejected");
+    }
+
+    #[test]
+    fn test_token_wrong_secret() {
+        let token = token_service(60).generate(Uuid::new_v4()).unwrap();
+
+        let other = TokenService {
+            secret_key: "another-secret-key".to_string(),
+            ttl: 60,
+            password_reset_ttl: 60,
+            email_verification_ttl: 60
+        };
+
+        assert!(other.verify(&token.token).is_err(), "Token signed with another key should be rejected");
+    }
 }
\ No newline at end of file
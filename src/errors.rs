@@ -1,15 +1,51 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use deadpool_postgres::PoolError;
+use serde::Serialize;
 use std::fmt;
 use tokio_postgres::error::Error;
 use tokio_pg_mapper;
-use juniper::{IntoFieldError, FieldError, Value};
+use juniper::{IntoFieldError, FieldError, Object, Value};
+
+/// An input field rejected by validation
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldViolation {
+    /// Input field name as seen by GraphQL clients, e.g. `postId`
+    pub field: String,
+    pub message: String,
+}
 
 #[derive(Debug, Clone)]
 pub enum AppErrorType {
     DbError,
-    #[allow(dead_code)]
+    /// Failure unrelated to the database or the input, e.g. signing a token
+    InternalError,
+    /// Email could not be delivered
+    MailError,
     NotFoundError,
-    InvalidField
+    InvalidField,
+    Unauthorized,
+    Forbidden,
+    /// Concurrent modification, e.g. a stale `expectedUpdatedAt`
+    Conflict,
+    /// Input rejected before reaching the database, with every violation
+    ValidationError(Vec<FieldViolation>)
+}
+
+impl AppErrorType {
+    /// Stable code exposed to clients, see `extensions.code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppErrorType::DbError => "DB_ERROR",
+            AppErrorType::InternalError => "INTERNAL_ERROR",
+            AppErrorType::MailError => "MAIL_ERROR",
+            AppErrorType::NotFoundError => "NOT_FOUND",
+            AppErrorType::InvalidField => "INVALID_FIELD",
+            AppErrorType::Unauthorized => "UNAUTHORIZED",
+            AppErrorType::Forbidden => "FORBIDDEN",
+            AppErrorType::Conflict => "CONFLICT",
+            AppErrorType::ValidationError(_) => "VALIDATION_ERROR",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -17,9 +53,18 @@ pub struct AppError {
     pub message: Option<String>,
     pub cause: Option<String>,
     pub error_type: AppErrorType,
+    /// Input field that caused the error, for `InvalidField` errors
+    pub field: Option<String>,
 }
 
 impl AppError {
+    pub fn with_field(self, field: &str) -> AppError {
+        AppError {
+            field: Some(field.to_string()),
+            ..self
+        }
+    }
+
     pub fn message(&self) -> String {
         match &*self {
             AppError {
@@ -34,14 +79,57 @@ impl AppError {
                 error_type: AppErrorType::InvalidField,
                 ..
             } => "Invalid field value provided".to_string(),
+            AppError {
+                error_type: AppErrorType::Unauthorized,
+                ..
+            } => "You must be logged in to perform this action".to_string(),
+            AppError {
+                error_type: AppErrorType::Forbidden,
+                ..
+            } => "You are not allowed to perform this action".to_string(),
+            AppError {
+                error_type: AppErrorType::Conflict,
+                ..
+            } => "The item was modified concurrently".to_string(),
+            AppError {
+                error_type: AppErrorType::ValidationError(_),
+                ..
+            } => "Invalid input provided".to_string(),
             _ => "An unexpected error has occurred".to_string(),
         }
     }
 }
 
+/// The `cause` extension is always set, see `hide_causes`
 impl IntoFieldError for AppError {
-    fn into_field_error(self) -> FieldError { 
-        FieldError::new(self.message(), Value::null())
+    fn into_field_error(self) -> FieldError {
+        let mut extensions = Object::with_capacity(3);
+
+        extensions.add_field("code", Value::from(self.error_type.code()));
+
+        if let Some(field) = &self.field {
+            extensions.add_field("field", Value::from(field.as_str()));
+        }
+
+        if let AppErrorType::ValidationError(violations) = &self.error_type {
+            let violations = violations
+                .iter()
+                .map(|violation| {
+                    let mut object = Object::with_capacity(2);
+                    object.add_field("field", Value::from(violation.field.as_str()));
+                    object.add_field("message", Value::from(violation.message.as_str()));
+                    Value::object(object)
+                })
+                .collect();
+
+            extensions.add_field("violations", Value::list(violations));
+        }
+
+        if let Some(cause) = &self.cause {
+            extensions.add_field("cause", Value::from(cause.as_str()));
+        }
+
+        FieldError::new(self.message(), Value::object(extensions))
     }
 }
 
@@ -51,6 +139,7 @@ impl From<PoolError> for AppError {
             message: None,
             cause: Some(error.to_string()),
             error_type: AppErrorType::DbError,
+            field: None
         }
     }
 }
@@ -61,6 +150,7 @@ impl From<Error> for AppError {
             message: None,
             cause: Some(error.to_string()),
             error_type: AppErrorType::DbError,
+            field: None
         }
     }
 }
@@ -71,8 +161,61 @@ impl From<tokio_pg_mapper::Error> for AppError {
             message: None,
             cause: Some(error.to_string()),
             error_type: AppErrorType::DbError,
+            field: None
+        }
+    }
+}
+
+/// Removes the internal `cause` from the errors of a serialized GraphQL response,
+/// done for every response unless `SERVER__DEBUG` is set
+pub fn hide_causes(response: &mut serde_json::Value) {
+    let errors = match response.get_mut("errors").and_then(serde_json::Value::as_array_mut) {
+        Some(errors) => errors,
+        None => return
+    };
+
+    for error in errors {
+        if let Some(extensions) = error.get_mut("extensions").and_then(serde_json::Value::as_object_mut) {
+            extensions.remove("cause");
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AppErrorResponse {
+    pub error: String,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<FieldViolation>,
+}
+
+/// Used by the plain HTTP handlers (feeds, sitemap, etc.), never exposes the cause
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self.error_type {
+            AppErrorType::DbError | AppErrorType::InternalError | AppErrorType::MailError =>
+                StatusCode::INTERNAL_SERVER_ERROR,
+            AppErrorType::NotFoundError => StatusCode::NOT_FOUND,
+            AppErrorType::InvalidField | AppErrorType::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AppErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppErrorType::Forbidden => StatusCode::FORBIDDEN,
+            AppErrorType::Conflict => StatusCode::CONFLICT,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(AppErrorResponse {
+            error: self.message(),
+            code: self.error_type.code(),
+            field: self.field.clone(),
+            violations: match &self.error_type {
+                AppErrorType::ValidationError(violations) => violations.clone(),
+                _ => vec![]
+            },
+        })
+    }
 }
 
 impl fmt::Display for AppError {
@@ -84,7 +227,8 @@ impl fmt::Display for AppError {
 #[cfg(test)]
 mod tests {
 
-    use super::{AppError, AppErrorType};
+    use super::{hide_causes, AppError, AppErrorType, FieldViolation};
+    use juniper::{graphql_value, IntoFieldError};
 
     #[test]
     fn test_default_db_error() {
@@ -92,6 +236,7 @@ mod tests {
             message: None,
             cause: None,
             error_type: AppErrorType::DbError,
+            field: None
         };
 
         assert_eq!(
@@ -107,6 +252,7 @@ mod tests {
             message: None,
             cause: None,
             error_type: AppErrorType::NotFoundError,
+            field: None
         };
 
         assert_eq!(
@@ -116,6 +262,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_default_unauthorized_error() {
+        let error = AppError {
+            message: None,
+            cause: None,
+            error_type: AppErrorType::Unauthorized,
+            field: None
+        };
+
+        assert_eq!(
+            error.message(),
+            "You must be logged in to perform this action".to_string(),
+            "Default message should be shown"
+        );
+    }
+
     #[test]
     fn test_user_db_error() {
         let user_message = "User-facing message".to_string();
@@ -124,6 +286,7 @@ mod tests {
             message: Some(user_message.clone()),
             cause: None,
             error_type: AppErrorType::DbError,
+            field: None
         };
 
         assert_eq!(
@@ -133,4 +296,62 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_field_error_extensions() {
+        let error = AppError {
+            message: None,
+            cause: Some("duplicate key value violates unique constraint".to_string()),
+            error_type: AppErrorType::InvalidField,
+            field: Some("slug".to_string())
+        };
+
+        assert_eq!(
+            error.into_field_error().extensions(),
+            &graphql_value!({
+                "code": "INVALID_FIELD",
+                "field": "slug",
+                "cause": "duplicate key value violates unique constraint"
+            }),
+            "Code, field and cause should be exposed"
+        );
+    }
+
+    #[test]
+    fn test_hide_causes() {
+        let mut response = serde_json::json!({
+            "data": null,
+            "errors": [{ "message": "Invalid field value provided", "extensions": { "code": "INVALID_FIELD", "cause": "secret" } }]
+        });
+
+        hide_causes(&mut response);
+
+        assert_eq!(
+            response["errors"][0]["extensions"],
+            serde_json::json!({ "code": "INVALID_FIELD" }),
+            "Cause should be removed"
+        );
+    }
+
+    #[test]
+    fn test_validation_error_extensions() {
+        let error = AppError {
+            message: None,
+            cause: None,
+            error_type: AppErrorType::ValidationError(vec![FieldViolation {
+                field: "title".to_string(),
+                message: "must not be blank".to_string()
+            }]),
+            field: None
+        };
+
+        assert_eq!(
+            error.into_field_error().extensions(),
+            &graphql_value!({
+                "code": "VALIDATION_ERROR",
+                "violations": [{ "field": "title", "message": "must not be blank" }]
+            }),
+            "Every violation should be listed"
+        );
+    }
+
 }
@@ -0,0 +1,314 @@
+/// RSS 2.0 and Atom feeds with the latest published posts
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use deadpool_postgres::Pool;
+use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}};
+use crate::{
+    config::Site,
+    errors::AppError,
+    models::post::Post,
+    repositories::{post::PostRepository, user::UserRepository}
+};
+
+const FEED_SIZE: i64 = 20;
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+const RSS_CONTENT_TYPE: &str = "application/rss+xml; charset=utf-8";
+const ATOM_CONTENT_TYPE: &str = "application/atom+xml; charset=utf-8";
+
+pub struct Feed {
+    pub title: String,
+    pub description: String,
+    /// HTML page the feed belongs to
+    pub link: String,
+    /// URL of the feed itself
+    pub self_link: String,
+    /// Posts with their author's username, newest first
+    pub entries: Vec<(Post, String)>,
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+impl Feed {
+    /// Last time any of the entries changed
+    pub fn updated(&self) -> Option<DateTime<Utc>> {
+        self.entries
+            .iter()
+            .map(|(post, _)| post.published_at.map_or(post.updated_at, |published_at| published_at.max(post.updated_at)))
+            .max()
+    }
+
+    /// Changes whenever an entry is added, removed or updated
+    pub fn etag(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+
+        for (post, _) in &self.entries {
+            post.id.hash(&mut hasher);
+            post.updated_at.hash(&mut hasher);
+            post.published_at.hash(&mut hasher);
+        }
+
+        format!("\"{:016x}\"", hasher.finish())
+    }
+
+    pub fn rss(&self, site: &Site) -> String {
+        let mut xml = String::new();
+
+        xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/">"#);
+        xml.push_str("<channel>");
+        xml.push_str(&format!("<title>{}</title>", escape(&self.title)));
+        xml.push_str(&format!("<link>{}</link>", escape(&self.link)));
+        xml.push_str(&format!("<description>{}</description>", escape(&self.description)));
+        xml.push_str(&format!(r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#, escape(&self.self_link)));
+
+        if let Some(updated) = self.updated() {
+            xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>", updated.to_rfc2822()));
+        }
+
+        for (post, author) in &self.entries {
+            xml.push_str("<item>");
+            xml.push_str(&format!("<title>{}</title>", escape(&post.title)));
+            xml.push_str(&format!("<link>{}</link>", escape(&site.post_url(&post.slug))));
+            xml.push_str(&format!(r#"<guid isPermaLink="false">urn:uuid:{}</guid>"#, post.id));
+            xml.push_str(&format!("<description>{}</description>", escape(&post.description)));
+            xml.push_str(&format!("<dc:creator>{}</dc:creator>", escape(author)));
+
+            if let Some(published_at) = post.published_at {
+                xml.push_str(&format!("<pubDate>{}</pubDate>", published_at.to_rfc2822()));
+            }
+
+            xml.push_str("</item>");
+        }
+
+        xml.push_str("</channel></rss>");
+        xml
+    }
+
+    pub fn atom(&self, site: &Site) -> String {
+        let updated = self.updated().unwrap_or_else(Utc::now);
+        let mut xml = String::new();
+
+        xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        xml.push_str(&format!("<title>{}</title>", escape(&self.title)));
+        xml.push_str(&format!("<subtitle>{}</subtitle>", escape(&self.description)));
+        xml.push_str(&format!(r#"<link href="{}" rel="self"/>"#, escape(&self.self_link)));
+        xml.push_str(&format!(r#"<link href="{}"/>"#, escape(&self.link)));
+        xml.push_str(&format!("<id>{}</id>", escape(&self.self_link)));
+        xml.push_str(&format!("<updated>{}</updated>", updated.to_rfc3339()));
+
+        for (post, author) in &self.entries {
+            xml.push_str("<entry>");
+            xml.push_str(&format!("<title>{}</title>", escape(&post.title)));
+            xml.push_str(&format!(r#"<link href="{}"/>"#, escape(&site.post_url(&post.slug))));
+            xml.push_str(&format!("<id>urn:uuid:{}</id>", post.id));
+
+            if let Some(published_at) = post.published_at {
+                xml.push_str(&format!("<published>{}</published>", published_at.to_rfc3339()));
+            }
+
+            xml.push_str(&format!("<updated>{}</updated>", post.updated_at.to_rfc3339()));
+            xml.push_str(&format!("<author><name>{}</name></author>", escape(author)));
+            xml.push_str(&format!("<summary>{}</summary>", escape(&post.description)));
+            xml.push_str("</entry>");
+        }
+
+        xml.push_str("</feed>");
+        xml
+    }
+}
+
+/// Whether the client's cached copy, identified by `If-None-Match` or `If-Modified-Since`, is still fresh
+fn is_fresh(req: &HttpRequest, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    let headers = req.headers();
+
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok()) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*");
+    }
+
+    match (headers.get(header::IF_MODIFIED_SINCE).and_then(|value| value.to_str().ok()), last_modified) {
+        (Some(since), Some(last_modified)) => NaiveDateTime::parse_from_str(since, HTTP_DATE_FORMAT)
+            .map(|since| last_modified.timestamp() <= since.timestamp())
+            .unwrap_or(false),
+        _ => false
+    }
+}
+
+/// Builds the response with caching headers, or a `304 Not Modified` when the client's copy is fresh
+pub fn cached_response(req: &HttpRequest, etag: String, last_modified: Option<DateTime<Utc>>, content_type: &str, body: impl FnOnce() -> String) -> HttpResponse {
+    let fresh = is_fresh(req, &etag, last_modified);
+
+    let mut response = if fresh { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+
+    response.header(header::ETAG, etag);
+
+    if let Some(last_modified) = last_modified {
+        response.header(header::LAST_MODIFIED, last_modified.format(HTTP_DATE_FORMAT).to_string());
+    }
+
+    if fresh {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body())
+    }
+}
+
+async fn blog_feed(pool: web::Data<Pool>, site: &Site, self_path: &str) -> Result<Feed, AppError> {
+    let entries = PostRepository::new(pool.into_inner())
+        .latest(None, FEED_SIZE)
+        .await?;
+
+    Ok(Feed {
+        title: site.title.clone(),
+        description: site.description.clone(),
+        link: site.url.clone(),
+        self_link: format!("{}{}", site.url, self_path),
+        entries
+    })
+}
+
+pub async fn rss(req: HttpRequest, pool: web::Data<Pool>, site: web::Data<Site>) -> Result<HttpResponse, AppError> {
+    let feed = blog_feed(pool, &site, "/feed.xml").await?;
+
+    Ok(cached_response(&req, feed.etag(), feed.updated(), RSS_CONTENT_TYPE, || feed.rss(&site)))
+}
+
+pub async fn atom(req: HttpRequest, pool: web::Data<Pool>, site: web::Data<Site>) -> Result<HttpResponse, AppError> {
+    let feed = blog_feed(pool, &site, "/atom.xml").await?;
+
+    Ok(cached_response(&req, feed.etag(), feed.updated(), ATOM_CONTENT_TYPE, || feed.atom(&site)))
+}
+
+pub async fn user_rss(
+    req: HttpRequest,
+    username: web::Path<String>,
+    pool: web::Data<Pool>,
+    site: web::Data<Site>
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.into_inner();
+    let user = UserRepository::new(pool.clone()).get_by_username(&username).await?;
+
+    let entries = PostRepository::new(pool)
+        .latest(Some(user.id), FEED_SIZE)
+        .await?;
+
+    let feed = Feed {
+        title: format!("{} - {}", user.username, site.title),
+        description: user.bio.clone().unwrap_or_else(|| format!("Posts by {}", user.username)),
+        link: site.user_url(&user.username),
+        self_link: format!("{}/users/{}/feed.xml", site.url, user.username),
+        entries
+    };
+
+    Ok(cached_response(&req, feed.etag(), feed.updated(), RSS_CONTENT_TYPE, || feed.rss(&site)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{escape, Feed};
+    use crate::{config::Site, models::post::{Post, PostStatus}};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn site() -> Site {
+        Site {
+            url: "http://127.0.0.1:8080".to_string(),
+            title: "Blog".to_string(),
+            description: "A <blog>".to_string()
+        }
+    }
+
+    fn post(title: &str, day: u32) -> Post {
+        let date = Utc.ymd(2020, 3, day).and_hms(5, 56, 36);
+
+        Post {
+            id: Uuid::new_v4(),
+            author_id: Uuid::new_v4(),
+            slug: "a-post".to_string(),
+            title: title.to_string(),
+            description: "Description".to_string(),
+            body: "Body".to_string(),
+            created_at: date,
+            updated_at: date,
+            status: PostStatus::Published,
+            published_at: Some(date),
+        }
+    }
+
+    fn feed(entries: Vec<(Post, String)>) -> Feed {
+        Feed {
+            title: "Blog".to_string(),
+            description: "A <blog>".to_string(),
+            link: "http://127.0.0.1:8080".to_string(),
+            self_link: "http://127.0.0.1:8080/feed.xml".to_string(),
+            entries
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("<a href=\"x\">Tom & Jerry's</a>"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;");
+    }
+
+    #[test]
+    fn test_rss() {
+        let rss = feed(vec![(post("Rust & Actix", 14), "user1".to_string())]).rss(&site());
+
+        assert!(rss.contains("<title>Rust &amp; Actix</title>"), "Titles should be escaped");
+        assert!(rss.contains("<link>http://127.0.0.1:8080/posts/a-post</link>"), "Items should link to the post");
+        assert!(rss.contains("<pubDate>Sat, 14 Mar 2020 05:56:36 +0000</pubDate>"), "Dates should be RFC 2822");
+        assert!(rss.contains("<dc:creator>user1</dc:creator>"), "Author should be included");
+    }
+
+    #[test]
+    fn test_atom() {
+        let atom = feed(vec![(post("Rust", 14), "user1".to_string())]).atom(&site());
+
+        assert!(atom.contains("<updated>2020-03-14T05:56:36+00:00</updated>"), "Dates should be RFC 3339");
+        assert!(atom.contains("<author><name>user1</name></author>"), "Author should be included");
+    }
+
+    #[test]
+    fn test_etag_changes_with_entries() {
+        let first = post("Rust", 14);
+        let mut updated = first.clone();
+        updated.updated_at = Utc.ymd(2020, 3, 20).and_hms(0, 0, 0);
+
+        let etag = feed(vec![(first.clone(), "user1".to_string())]).etag();
+
+        assert_eq!(etag, feed(vec![(first, "user1".to_string())]).etag(), "Same entries should have the same ETag");
+        assert_ne!(etag, feed(vec![(updated, "user1".to_string())]).etag(), "Updated entries should change the ETag");
+        assert_ne!(etag, feed(vec![]).etag(), "Removed entries should change the ETag");
+    }
+
+    #[test]
+    fn test_updated() {
+        let entries = vec![(post("New", 20), "user1".to_string()), (post("Old", 14), "user1".to_string())];
+
+        assert_eq!(
+            feed(entries).updated(),
+            Some(Utc.ymd(2020, 3, 20).and_hms(5, 56, 36)),
+            "The most recent change should be used"
+        );
+    }
+}
@@ -1,21 +1,58 @@
-use deadpool_postgres::Pool;
+use deadpool_postgres::{Client, Pool};
 use juniper::RootNode;
-use crate::errors::AppError;
-use crate::repositories::{post::{PostRepository, PostLoader}, user::UserRepository};
-use crate::config::HashingService;
-use crate::models::{post::{CreatePost, Post}, user::{User, CreateUser}};
-use std::sync::Arc;
+use crate::errors::{AppError, AppErrorType};
+use crate::repositories::{
+    comment::{CommentRepository, CommentLoader},
+    one_time_token::{invalid_token_error, OneTimeTokenRepository},
+    post::{PostRepository, PostLoader, UserPostsKey},
+    tag::{TagRepository, TagLoader},
+    user::{UserRepository, UserLoader}
+};
+use super::guards::Guard;
+use crate::config::{AuthPolicy, HashingService, Site, TokenService};
+use crate::mailer::{Email, Mailer};
+use crate::one_time_token::{OneTimeToken, TokenPurpose};
+use crate::markdown::{Field, MarkdownRenderer};
+use crate::models::{
+    comment::{Comment, CreateComment},
+    pagination::{Connection, Edge, PageInfo, PageRequest},
+    post::{CreatePost, Post, PostStatus, SearchHit, UpdatePost},
+    tag::{Tag, TagCount},
+    user::{AuthPayload, User, CreateUser, Role, PASSWORD_RULES},
+    validation::{Validate, Validator}
+};
+use std::sync::{Arc, Mutex};
+use slog_scope::error;
 use uuid::Uuid;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 
 #[derive(Clone)]
 pub struct Context {
     pub pool: Arc<Pool>,
     pub hashing: Arc<HashingService>,
-    pub post_loader: PostLoader
+    pub tokens: Arc<TokenService>,
+    pub markdown: Arc<MarkdownRenderer>,
+    pub mailer: Arc<dyn Mailer>,
+    pub site: Arc<Site>,
+    pub policy: AuthPolicy,
+    pub current_user: Option<User>,
+    /// Why the request's access token was rejected, returned where a user is required
+    pub auth_error: Option<AppError>,
+    /// User who proved their identity earlier in this request by logging in, signing up
+    /// or verifying their email, before holding an access token
+    pub identified_user: Arc<Mutex<Option<User>>>,
+    pub post_loader: PostLoader,
+    pub user_loader: UserLoader,
+    pub comment_loader: CommentLoader,
+    pub tag_loader: TagLoader
 }
 
 impl Context {
+    /// Pooled client, for writes that span several repositories in one transaction
+    pub async fn client(&self) -> Result<Client, AppError> {
+        Ok(self.pool.get().await?)
+    }
+
     pub fn user_repository(&self) -> UserRepository {
         UserRepository::new(self.pool.clone())
     }
@@ -23,6 +60,143 @@ impl Context {
     pub fn post_repository(&self) -> PostRepository {
         PostRepository::new(self.pool.clone())
     }
+
+    pub fn comment_repository(&self) -> CommentRepository {
+        CommentRepository::new(self.pool.clone())
+    }
+
+    pub fn tag_repository(&self) -> TagRepository {
+        TagRepository::new(self.pool.clone())
+    }
+
+    pub fn one_time_token_repository(&self) -> OneTimeTokenRepository {
+        OneTimeTokenRepository::new(self.pool.clone())
+    }
+
+    /// Issues a new verification token and emails the link to the user
+    pub async fn send_verification_email(&self, user: &User) -> Result<(), AppError> {
+        let (token, expires_at) = self.tokens.email_verification_token();
+
+        self.one_time_token_repository()
+            .create(TokenPurpose::EmailVerification, user.id, &token.hash, expires_at)
+            .await?;
+
+        self.mailer.send(Email {
+            to: user.email.clone(),
+            subject: format!("Verify your {} email address", self.site.title),
+            body: format!(
+                "Hi {},\n\nFollow this link to verify your email address:\n{}",
+                user.username,
+                self.site.email_verification_url(&token.token)
+            )
+        }).await
+    }
+
+    /// Issues a new password reset token and emails the link to the user
+    pub async fn send_password_reset_email(&self, user: &User) -> Result<(), AppError> {
+        let (token, expires_at) = self.tokens.password_reset_token();
+
+        self.one_time_token_repository()
+            .create(TokenPurpose::PasswordReset, user.id, &token.hash, expires_at)
+            .await?;
+
+        self.mailer.send(Email {
+            to: user.email.clone(),
+            subject: format!("Reset your {} password", self.site.title),
+            body: format!(
+                "Hi {},\n\nFollow this link to choose a new password:\n{}\n\nIf you didn't ask for it, you can ignore this email.",
+                user.username,
+                self.site.password_reset_url(&token.token)
+            )
+        }).await
+    }
+
+    /// Only reveals the user's private fields for the rest of the request, see `guarded`,
+    /// it doesn't authorize anything
+    pub fn identify(&self, user: &User) {
+        *self.identified_user.lock().unwrap() = Some(user.clone());
+    }
+
+    /// Returns the authenticated user or an `Unauthorized` error
+    pub fn require_user(&self) -> Result<&User, AppError> {
+        self.current_user.as_ref().ok_or_else(|| {
+            self.auth_error.clone().unwrap_or(AppError {
+                message: None,
+                cause: None,
+                error_type: AppErrorType::Unauthorized,
+                field: None
+            })
+        })
+    }
+
+    /// Returns the authenticated user if allowed to write posts,
+    /// which may require a verified email address
+    pub fn require_author(&self) -> Result<&User, AppError> {
+        let user = self.authorize(Guard::Role(Role::Author))?;
+
+        if self.policy.require_verified_email && !user.is_email_verified() {
+            return Err(AppError {
+                message: Some("You must verify your email address before posting".to_string()),
+                cause: None,
+                error_type: AppErrorType::Forbidden,
+                field: None
+            });
+        }
+
+        Ok(user)
+    }
+
+    /// Unpublished posts are only visible to their author and editors
+    pub fn can_view(&self, post: &Post) -> bool {
+        post.is_published() || self.allows(&Guard::owner_or_editor(post.author_id))
+    }
+
+    /// Hides unpublished posts from everyone else
+    pub fn visible(&self, post: Post) -> Result<Post, AppError> {
+        if self.can_view(&post) {
+            Ok(post)
+        } else {
+            Err(AppError {
+                message: None,
+                cause: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+        }
+    }
+
+    pub fn allows(&self, guard: &Guard) -> bool {
+        guard.allows(self.current_user.as_ref())
+    }
+
+    /// Returns the authenticated user if the guard passes,
+    /// `Unauthorized` for anonymous callers and `Forbidden` otherwise
+    pub fn authorize(&self, guard: Guard) -> Result<&User, AppError> {
+        let user = self.require_user()?;
+
+        if self.allows(&guard) {
+            Ok(user)
+        } else {
+            Err(AppError {
+                message: None,
+                cause: None,
+                error_type: AppErrorType::Forbidden,
+                field: None
+            })
+        }
+    }
+
+    /// For private fields: the value if the guard passes, `null` otherwise
+    /// Also passes for the identified user, so `login` and `createUser` return the caller's own email
+    pub fn guarded<T>(&self, guard: Guard, value: T) -> Option<T> {
+        let identified = self.identified_user.lock().unwrap();
+
+        if self.allows(&guard) || guard.allows(identified.as_ref()) {
+            Some(value)
+        } else {
+            None
+        }
+    }
 }
 
 /// Context Marker
@@ -39,20 +213,60 @@ impl Query {
         "1.0"
     }
 
-    pub async fn users(context: &Context) -> Result<Vec<User>, AppError> {
-        context.user_repository().all().await
+    /// The authenticated user, if any
+    pub fn me(context: &Context) -> Option<User> {
+        context.current_user.clone()
+    }
+
+    pub async fn users(
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        context: &Context
+    ) -> Result<Connection<User>, AppError> {
+        let page = PageRequest::new(first, after, last, before);
+        context.user_repository().all(&page).await
     }
 
     pub async fn user(id: Uuid, context: &Context) -> Result<User, AppError> {
         context.user_repository().get(id).await
     }
 
-    pub async fn posts(context: &Context) -> Result<Vec<Post>, AppError> {
-        context.post_repository().all().await
+    pub async fn posts(
+        tag: Option<String>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        context: &Context
+    ) -> Result<Connection<Post>, AppError> {
+        let page = PageRequest::new(first, after, last, before);
+        context.post_repository().all(tag, &page).await
     }
 
     pub async fn post(id: Uuid, context: &Context) -> Result<Post, AppError> {
-        context.post_repository().get(id).await
+        let post = context.post_repository().get(id).await?;
+        context.visible(post)
+    }
+
+    pub async fn post_by_slug(slug: String, context: &Context) -> Result<Post, AppError> {
+        let post = context.post_repository().get_by_slug(&slug).await?;
+        context.visible(post)
+    }
+
+    /// Full-text search over published posts' title, description and body
+    pub async fn search_posts(
+        query: String,
+        first: Option<i32>,
+        after: Option<String>,
+        context: &Context
+    ) -> Result<Connection<SearchHit>, AppError> {
+        context.post_repository().search(&query, first, after).await
+    }
+
+    pub async fn tags(context: &Context) -> Result<Vec<TagCount>, AppError> {
+        context.tag_repository().all().await
     }
 }
 
@@ -60,9 +274,20 @@ impl Query {
     Context = Context
 )]
 impl User {
-    pub async fn posts(&self, context: &Context) -> Result<Vec<Post>, AppError> {
-        // context.post_repository().get_for_user(self.id).await
-        context.post_loader.load(self.id).await
+    pub async fn posts(
+        &self,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        context: &Context
+    ) -> Result<Connection<Post>, AppError> {
+        let key = UserPostsKey {
+            author_id: self.id,
+            page: PageRequest::new(first, after, last, before),
+            include_unpublished: context.allows(&Guard::owner_or_editor(self.id))
+        };
+        context.post_loader.load(key).await
     }
 
     pub fn id(&self) -> Uuid {
@@ -73,8 +298,18 @@ impl User {
         self.username.as_str()
     }
 
-    pub fn email(&self) -> &str {
-        self.email.as_str()
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Only visible to the user themself and admins
+    pub fn email(&self, context: &Context) -> Option<&str> {
+        context.guarded(Guard::self_or_admin(self.id), self.email.as_str())
+    }
+
+    /// Only visible to the user themself and admins
+    pub fn email_verified(&self, context: &Context) -> Option<bool> {
+        context.guarded(Guard::self_or_admin(self.id), self.is_email_verified())
     }
 
     pub fn bio(&self) -> Option<&str> {
@@ -85,14 +320,254 @@ impl User {
         self.image.as_deref()
     }
 
-    pub fn created_at(&self) -> NaiveDateTime {
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    pub fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+
+}
+
+#[juniper::graphql_object(
+    Context = Context
+)]
+impl Post {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn author_id(&self) -> Uuid {
+        self.author_id
+    }
+
+    pub async fn author(&self, context: &Context) -> Result<User, AppError> {
+        context.user_loader.load(self.author_id).await
+    }
+
+    pub fn slug(&self) -> &str {
+        self.slug.as_str()
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.as_str()
+    }
+
+    pub fn description(&self) -> &str {
+        self.description.as_str()
+    }
+
+    /// Description rendered from markdown to sanitized HTML
+    pub fn description_html(&self, context: &Context) -> String {
+        context.markdown.render(self.id, Field::Description, self.updated_at, &self.description)
+    }
+
+    pub fn body(&self) -> &str {
+        self.body.as_str()
+    }
+
+    /// Body rendered from markdown to sanitized HTML
+    pub fn body_html(&self, context: &Context) -> String {
+        context.markdown.render(self.id, Field::Body, self.updated_at, &self.body)
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    pub fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+
+    pub fn status(&self) -> PostStatus {
+        self.effective_status()
+    }
+
+    pub fn published_at(&self) -> Option<DateTime<Utc>> {
+        self.published_at
+    }
+
+    pub async fn comments(&self, context: &Context) -> Result<Vec<Comment>, AppError> {
+        context.comment_loader.load(self.id).await
+    }
+
+    pub async fn tags(&self, context: &Context) -> Result<Vec<Tag>, AppError> {
+        context.tag_loader.load(self.id).await
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context
+)]
+impl TagCount {
+    pub fn tag(&self) -> Tag {
+        self.tag.clone()
+    }
+
+    pub fn post_count(&self) -> i32 {
+        self.post_count as i32
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context
+)]
+impl Comment {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub async fn author(&self, context: &Context) -> Result<User, AppError> {
+        context.user_loader.load(self.author_id).await
+    }
+
+    pub async fn post(&self, context: &Context) -> Result<Post, AppError> {
+        let post = context.post_repository().get(self.post_id).await?;
+        context.visible(post)
+    }
+
+    pub fn body(&self) -> &str {
+        self.body.as_str()
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
         self.created_at
     }
 
-    pub fn updated_at(&self) -> NaiveDateTime {
+    pub fn updated_at(&self) -> DateTime<Utc> {
         self.updated_at
     }
+}
 
+#[juniper::graphql_object(
+    Context = Context,
+    name = "UserConnection"
+)]
+impl Connection<User> {
+    pub fn edges(&self) -> Vec<Edge<User>> {
+        self.edges.clone()
+    }
+
+    pub fn page_info(&self) -> PageInfo {
+        self.page_info.clone()
+    }
+
+    pub fn total_count(&self) -> i32 {
+        self.total_count as i32
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context,
+    name = "UserEdge"
+)]
+impl Edge<User> {
+    pub fn cursor(&self) -> &str {
+        self.cursor.as_str()
+    }
+
+    pub fn node(&self) -> User {
+        self.node.clone()
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context,
+    name = "PostConnection"
+)]
+impl Connection<Post> {
+    pub fn edges(&self) -> Vec<Edge<Post>> {
+        self.edges.clone()
+    }
+
+    pub fn page_info(&self) -> PageInfo {
+        self.page_info.clone()
+    }
+
+    pub fn total_count(&self) -> i32 {
+        self.total_count as i32
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context,
+    name = "PostEdge"
+)]
+impl Edge<Post> {
+    pub fn cursor(&self) -> &str {
+        self.cursor.as_str()
+    }
+
+    pub fn node(&self) -> Post {
+        self.node.clone()
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context,
+    name = "SearchConnection"
+)]
+impl Connection<SearchHit> {
+    pub fn edges(&self) -> Vec<Edge<SearchHit>> {
+        self.edges.clone()
+    }
+
+    pub fn page_info(&self) -> PageInfo {
+        self.page_info.clone()
+    }
+
+    pub fn total_count(&self) -> i32 {
+        self.total_count as i32
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context,
+    name = "SearchEdge"
+)]
+impl Edge<SearchHit> {
+    pub fn cursor(&self) -> &str {
+        self.cursor.as_str()
+    }
+
+    pub fn node(&self) -> SearchHit {
+        self.node.clone()
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context
+)]
+impl SearchHit {
+    pub fn post(&self) -> Post {
+        self.post.clone()
+    }
+
+    pub fn rank(&self) -> f64 {
+        self.rank as f64
+    }
+
+    pub fn snippet(&self) -> &str {
+        self.snippet.as_str()
+    }
+}
+
+#[juniper::graphql_object(
+    Context = Context
+)]
+impl AuthPayload {
+    pub fn token(&self) -> &str {
+        self.token.as_str()
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    pub fn user(&self) -> &User {
+        &self.user
+    }
 }
 
 pub struct Mutation {}
@@ -101,12 +576,201 @@ pub struct Mutation {}
     Context = Context,
 )]
 impl Mutation {
+    /// New users start unverified and are emailed a verification link
     pub async fn create_user(input: CreateUser, context: &Context) -> Result<User, AppError> {
-        context.user_repository().create(input, context.hashing.clone()).await
+        input.validate()?;
+
+        let user = context.user_repository().create(input, context.hashing.clone()).await?;
+
+        // The account exists regardless, the email can be sent again
+        if let Err(err) = context.send_verification_email(&user).await {
+            error!("Error sending verification email {:?}", err; "user" => user.id.to_string());
+        }
+
+        context.identify(&user);
+
+        Ok(user)
+    }
+
+    pub async fn verify_email(token: String, context: &Context) -> Result<User, AppError> {
+        let user = context.one_time_token_repository()
+            .verify_email(&OneTimeToken::hash(&token))
+            .await?;
+
+        context.identify(&user);
+
+        Ok(user)
+    }
+
+    pub async fn resend_verification_email(context: &Context) -> Result<bool, AppError> {
+        let user = context.require_user()?;
+
+        if user.is_email_verified() {
+            return Err(AppError {
+                message: Some("Email address already verified.".to_string()),
+                cause: None,
+                error_type: AppErrorType::InvalidField,
+                field: None
+            });
+        }
+
+        context.send_verification_email(user).await?;
+
+        Ok(true)
+    }
+
+    pub async fn login(username_or_email: String, password: String, context: &Context) -> Result<AuthPayload, AppError> {
+        let user = context.user_repository()
+            .authenticate(username_or_email, password, context.hashing.clone())
+            .await?;
+
+        let access_token = context.tokens.generate(user.id)?;
+
+        context.identify(&user);
+
+        Ok(AuthPayload {
+            token: access_token.token,
+            expires_at: access_token.expires_at,
+            user
+        })
+    }
+
+    /// Emails a password reset link
+    /// Succeeds for unknown addresses too, so it can't be used to find accounts
+    pub async fn request_password_reset(email: String, context: &Context) -> Result<bool, AppError> {
+        let user = match context.user_repository().get_by_email(&email).await {
+            Ok(user) => user,
+            Err(AppError { error_type: AppErrorType::NotFoundError, .. }) => return Ok(true),
+            Err(err) => return Err(err)
+        };
+
+        // Sent in the background so existing accounts respond as fast as unknown ones, errors are only logged
+        let context = context.clone();
+        actix_rt::spawn(async move {
+            if let Err(err) = context.send_password_reset_email(&user).await {
+                error!("Error sending password reset email {:?}", err; "user" => user.id.to_string());
+            }
+        });
+
+        Ok(true)
+    }
+
+    /// Access tokens issued before the reset stop working, signing out other sessions
+    pub async fn reset_password(token: String, new_password: String, context: &Context) -> Result<bool, AppError> {
+        Validator::new()
+            .field("newPassword", &new_password, PASSWORD_RULES)
+            .finish()?;
+
+        let token_hash = OneTimeToken::hash(&token);
+        let repository = context.one_time_token_repository();
+
+        // Checked before hashing, so invalid tokens can't be used to keep the hashing pool busy
+        if !repository.is_valid(TokenPurpose::PasswordReset, &token_hash).await? {
+            return Err(invalid_token_error(TokenPurpose::PasswordReset));
+        }
+
+        let password_hash = context.hashing.hash(new_password).await?;
+
+        repository
+            .reset_password(&token_hash, &password_hash)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Admins only, and not on themselves; the last admin can never be demoted
+    pub async fn set_user_role(id: Uuid, role: Role, context: &Context) -> Result<User, AppError> {
+        let admin = context.authorize(Guard::Role(Role::Admin))?;
+
+        if admin.id == id {
+            return Err(AppError {
+                message: Some("You can't change your own role.".to_string()),
+                cause: None,
+                error_type: AppErrorType::Forbidden,
+                field: Some("id".to_string())
+            });
+        }
+
+        context.user_repository().set_role(id, role).await
     }
 
     pub async fn create_post(input: CreatePost, context: &Context) -> Result<Post, AppError> {
-        context.post_repository().create(input).await
+        let author = context.require_author()?;
+        input.validate()?;
+
+        let tags = input.tags.clone();
+        let mut client = context.client().await?;
+        let transaction = client.transaction().await?;
+
+        let post = context.post_repository().create(&transaction, author.id, input).await?;
+
+        if let Some(tags) = tags {
+            context.tag_repository().set_post_tags(&transaction, post.id, &tags).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(post)
+    }
+
+    pub async fn update_post(id: Uuid, input: UpdatePost, context: &Context) -> Result<Post, AppError> {
+        input.validate()?;
+
+        let post = context.post_repository().get(id).await?;
+        context.authorize(Guard::post_editor(post.author_id))?;
+
+        let tags = input.tags.clone();
+        let mut client = context.client().await?;
+        let transaction = client.transaction().await?;
+
+        let post = context.post_repository().update(&transaction, id, input).await?;
+
+        if let Some(tags) = tags {
+            context.tag_repository().set_post_tags(&transaction, post.id, &tags).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(post)
+    }
+
+    pub async fn delete_post(id: Uuid, context: &Context) -> Result<Post, AppError> {
+        let post = context.post_repository().get(id).await?;
+        context.authorize(Guard::post_editor(post.author_id))?;
+        context.post_repository().delete(id).await
+    }
+
+    /// Publishes the post now, or schedules it when `publish_at` is in the future
+    pub async fn publish_post(id: Uuid, publish_at: Option<DateTime<Utc>>, context: &Context) -> Result<Post, AppError> {
+        let post = context.post_repository().get(id).await?;
+        context.authorize(Guard::post_editor(post.author_id))?;
+
+        let now = Utc::now();
+        let publish_at = publish_at.unwrap_or(now);
+        let status = if publish_at > now { PostStatus::Scheduled } else { PostStatus::Published };
+
+        context.post_repository().set_status(id, status, Some(publish_at)).await
+    }
+
+    pub async fn unpublish_post(id: Uuid, context: &Context) -> Result<Post, AppError> {
+        let post = context.post_repository().get(id).await?;
+        context.authorize(Guard::post_editor(post.author_id))?;
+        context.post_repository().set_status(id, PostStatus::Draft, None).await
+    }
+
+    pub async fn create_comment(input: CreateComment, context: &Context) -> Result<Comment, AppError> {
+        let author = context.require_user()?;
+        input.validate()?;
+
+        let post = context.post_repository().get(input.post_id).await?;
+        context.visible(post)?;
+        context.comment_repository().create(author.id, input).await
+    }
+
+    pub async fn delete_comment(id: Uuid, context: &Context) -> Result<Comment, AppError> {
+        let comment = context.comment_repository().get(id).await?;
+        context.authorize(Guard::owner_or_editor(comment.author_id))?;
+        context.comment_repository().delete(id).await
     }
 }
 
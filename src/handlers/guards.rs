@@ -0,0 +1,98 @@
+/// Authorization rules shared by resolvers, both for mutations and private fields
+
+use uuid::Uuid;
+use crate::models::user::{Role, User};
+
+pub enum Guard {
+    /// The user with this id
+    User(Uuid),
+    /// Users with this role or a more privileged one
+    Role(Role),
+    AnyOf(Vec<Guard>),
+    AllOf(Vec<Guard>),
+}
+
+impl Guard {
+    /// The user themself or an admin, e.g. for profile fields like `email`
+    pub fn self_or_admin(user_id: Uuid) -> Guard {
+        Guard::AnyOf(vec![Guard::User(user_id), Guard::Role(Role::Admin)])
+    }
+
+    /// Authors may change their own posts, editors any post
+    pub fn post_editor(author_id: Uuid) -> Guard {
+        Guard::AnyOf(vec![
+            Guard::AllOf(vec![Guard::User(author_id), Guard::Role(Role::Author)]),
+            Guard::Role(Role::Editor),
+        ])
+    }
+
+    /// The author of a resource or an editor, e.g. to see drafts or moderate comments
+    pub fn owner_or_editor(author_id: Uuid) -> Guard {
+        Guard::AnyOf(vec![Guard::User(author_id), Guard::Role(Role::Editor)])
+    }
+
+    pub fn allows(&self, user: Option<&User>) -> bool {
+        match (self, user) {
+            (_, None) => false,
+            (Guard::User(id), Some(user)) => user.id == *id,
+            (Guard::Role(role), Some(user)) => user.has_role(*role),
+            (Guard::AnyOf(guards), user) => guards.iter().any(|guard| guard.allows(user)),
+            (Guard::AllOf(guards), user) => guards.iter().all(|guard| guard.allows(user)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Guard;
+    use crate::models::user::{Role, User};
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn user(role: Role) -> User {
+        User {
+            id: Uuid::new_v4(),
+            username: "user1".to_string(),
+            email: "user1@example.com".to_string(),
+            password: "hash".to_string(),
+            bio: None,
+            image: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            password_changed_at: None,
+            email_verified_at: None,
+            role,
+        }
+    }
+
+    #[test]
+    fn test_anonymous() {
+        assert!(!Guard::Role(Role::Reader).allows(None), "Anonymous callers have no role");
+        assert!(!Guard::self_or_admin(Uuid::new_v4()).allows(None), "Anonymous callers are nobody");
+    }
+
+    #[test]
+    fn test_self_or_admin() {
+        let owner = user(Role::Reader);
+        let other = user(Role::Editor);
+        let admin = user(Role::Admin);
+        let guard = Guard::self_or_admin(owner.id);
+
+        assert!(guard.allows(Some(&owner)), "Users can see their own fields");
+        assert!(guard.allows(Some(&admin)), "Admins can see every user's fields");
+        assert!(!guard.allows(Some(&other)), "Other users can't see private fields");
+    }
+
+    #[test]
+    fn test_post_editor() {
+        let author = user(Role::Author);
+        let demoted = user(Role::Reader);
+
+        assert!(Guard::post_editor(author.id).allows(Some(&author)), "Authors can edit their own posts");
+        assert!(!Guard::post_editor(Uuid::new_v4()).allows(Some(&author)), "Authors can't edit other posts");
+        assert!(!Guard::post_editor(demoted.id).allows(Some(&demoted)), "Readers can't edit posts");
+        assert!(Guard::post_editor(author.id).allows(Some(&user(Role::Editor))), "Editors can edit any post");
+        assert!(Guard::post_editor(author.id).allows(Some(&user(Role::Admin))), "Admins are editors too");
+    }
+}
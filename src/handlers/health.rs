@@ -0,0 +1,80 @@
+/// Liveness and readiness probes
+
+use actix_rt::time::timeout;
+use actix_web::{web, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+use serde::Serialize;
+use slog_scope::error;
+use std::time::Duration;
+use crate::errors::AppError;
+
+/// Upper bound for getting a client and running the readiness query
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+pub struct PoolStatus {
+    pub max_size: usize,
+    pub size: usize,
+    pub available: usize,
+    /// Tasks waiting for a client
+    pub waiting: usize,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub pool: PoolStatus,
+}
+
+impl From<&Pool> for PoolStatus {
+    fn from(pool: &Pool) -> PoolStatus {
+        let status = pool.status();
+
+        // deadpool reports waiting tasks as negative availability
+        PoolStatus {
+            max_size: status.max_size,
+            size: status.size,
+            available: status.available.max(0) as usize,
+            waiting: (-status.available).max(0) as usize,
+        }
+    }
+}
+
+async fn ping(pool: &Pool) -> Result<(), AppError> {
+    let client: Client = pool.get().await?;
+
+    client.simple_query("select 1").await?;
+
+    Ok(())
+}
+
+/// Liveness, doesn't touch the database
+pub async fn health() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+/// Readiness, 503 when the database can't be reached in time
+pub async fn ready(pool: web::Data<Pool>) -> HttpResponse {
+    let result = match timeout(READY_TIMEOUT, ping(&pool)).await {
+        Ok(result) => result.map_err(|err| err.cause.unwrap_or_else(|| err.message())),
+        Err(_) => Err(format!("Timed out after {}ms", READY_TIMEOUT.as_millis())),
+    };
+
+    if let Err(err) = &result {
+        error!("Readiness check failed {}", err);
+    }
+
+    let readiness = Readiness {
+        ready: result.is_ok(),
+        error: result.err(),
+        pool: PoolStatus::from(pool.get_ref()),
+    };
+
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
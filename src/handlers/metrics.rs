@@ -0,0 +1,18 @@
+use actix_web::{web, HttpResponse};
+use deadpool_postgres::Pool;
+use super::health::PoolStatus;
+use crate::metrics::{render, DB_POOL};
+
+pub async fn metrics(pool: web::Data<Pool>) -> HttpResponse {
+    // Pool utilization is sampled on scrape
+    let status = PoolStatus::from(pool.get_ref());
+
+    DB_POOL.with_label_values(&["max"]).set(status.max_size as i64);
+    DB_POOL.with_label_values(&["size"]).set(status.size as i64);
+    DB_POOL.with_label_values(&["available"]).set(status.available as i64);
+    DB_POOL.with_label_values(&["waiting"]).set(status.waiting as i64);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(render())
+}
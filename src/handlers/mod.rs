@@ -1,15 +1,30 @@
+mod feeds;
 mod graphql;
+mod guards;
+mod health;
+mod metrics;
+mod sitemap;
 
-use actix_web::{web, HttpResponse};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
 use deadpool_postgres::Pool;
-use juniper::http::{graphiql::graphiql_source, GraphQLRequest};
-use std::sync::Arc;
+use juniper::{http::{graphiql::graphiql_source, GraphQLRequest, GraphQLResponse}, InputValue, IntoFieldError};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
 use graphql::{create_schema, Schema, Context};
-use crate::{repositories::post::get_post_loader, config::HashingService};
-
-async fn health() -> HttpResponse {
-    HttpResponse::Ok().finish()
-}
+use crate::{
+    repositories::{
+        comment::get_comment_loader,
+        post::get_post_loader,
+        tag::get_tag_loader,
+        user::{get_user_loader, UserRepository}
+    },
+    config::{AuthPolicy, ErrorReporting, HashingService, Site, TokenService},
+    mailer::Mailer,
+    markdown::MarkdownRenderer,
+    metrics::{root_fields, GRAPHQL_DURATION},
+    errors::{hide_causes, AppError, AppErrorType},
+    models::user::User
+};
 
 pub fn app_config(config: &mut web::ServiceConfig) {
     let schema = create_schema();
@@ -17,7 +32,14 @@ pub fn app_config(config: &mut web::ServiceConfig) {
         .data(schema)
         .service(web::resource("/graphql").route(web::post().to(graphql)))
         .service(web::resource("/graphiql").route(web::get().to(graphiql)))
-        .service(web::resource("/").route(web::get().to(health)));
+        .service(web::resource("/feed.xml").route(web::get().to(feeds::rss)))
+        .service(web::resource("/atom.xml").route(web::get().to(feeds::atom)))
+        .service(web::resource("/users/{username}/feed.xml").route(web::get().to(feeds::user_rss)))
+        .service(web::resource("/sitemap.xml").route(web::get().to(sitemap::sitemap)))
+        .service(web::resource("/sitemaps/{page}.xml").route(web::get().to(sitemap::sitemap_page)))
+        .service(web::resource("/metrics").route(web::get().to(metrics::metrics)))
+        .service(web::resource("/ready").route(web::get().to(health::ready)))
+        .service(web::resource("/").route(web::get().to(health::health)));
 }
 
 async fn graphiql() -> HttpResponse {
@@ -27,17 +49,148 @@ async fn graphiql() -> HttpResponse {
         .body(html)
 }
 
+/// Resolves the user identified by the `Authorization: Bearer <token>` header, if any
+async fn authenticate(req: &HttpRequest, tokens: &TokenService, pool: Arc<Pool>) -> Result<Option<User>, AppError> {
+    let header = match req.headers().get(header::AUTHORIZATION) {
+        Some(header) => header,
+        None => return Ok(None)
+    };
+
+    let invalid_header = AppError {
+        message: Some("Invalid authorization header".to_string()),
+        cause: None,
+        error_type: AppErrorType::Unauthorized,
+        field: None
+    };
+
+    let value = header.to_str().map_err(|_| invalid_header.clone())?;
+    let mut parts = value.splitn(2, ' ');
+
+    let token = match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        _ => return Err(invalid_header)
+    };
+
+    let claims = tokens.verify(token)?;
+    let user_id = claims.user_id;
+
+    match UserRepository::new(pool).get(user_id).await {
+        Ok(user) if user.accepts_token_issued_at(claims.issued_at) => Ok(Some(user)),
+        Ok(_) => Err(AppError {
+            message: Some("Invalid or expired access token".to_string()),
+            cause: Some(format!("Password of user {} changed after the token was issued", user_id)),
+            error_type: AppErrorType::Unauthorized,
+            field: None
+        }),
+        Err(AppError { error_type: AppErrorType::NotFoundError, .. }) => Err(AppError {
+            message: Some("Invalid or expired access token".to_string()),
+            cause: Some(format!("User {} no longer exists", user_id)),
+            error_type: AppErrorType::Unauthorized,
+            field: None
+        }),
+        Err(err) => Err(err)
+    }
+}
+
+/// Body of a GraphQL request, read before it is handed to juniper to label metrics
+#[derive(Deserialize)]
+struct GraphQLBody {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<InputValue>
+}
+
 async fn graphql(
-    data: web::Json<GraphQLRequest>,
+    req: HttpRequest,
+    body: web::Json<GraphQLBody>,
     schema: web::Data<Schema>,
     pool: web::Data<Pool>,
-    hashing_service: web::Data<HashingService>
+    hashing_service: web::Data<HashingService>,
+    token_service: web::Data<TokenService>,
+    markdown: web::Data<MarkdownRenderer>,
+    mailer: web::Data<Arc<dyn Mailer>>,
+    site: web::Data<Site>,
+    policy: web::Data<AuthPolicy>,
+    error_reporting: web::Data<ErrorReporting>
 ) -> HttpResponse {
     let pool: Arc<Pool> = pool.into_inner();
     let hashing: Arc<HashingService> = hashing_service.into_inner();
+    let tokens: Arc<TokenService> = token_service.into_inner();
+    let markdown: Arc<MarkdownRenderer> = markdown.into_inner();
+    let mailer: Arc<dyn Mailer> = mailer.get_ref().clone();
+    let site: Arc<Site> = site.into_inner();
+
+    // Invalid tokens don't fail the request, so a client holding a stale token can still log in;
+    // the error is reported by the fields that require a user
+    let (current_user, auth_error) = match authenticate(&req, &tokens, pool.clone()).await {
+        Ok(user) => (user, None),
+        Err(err @ AppError { error_type: AppErrorType::Unauthorized, .. }) => (None, Some(err)),
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .json(graphql_response(GraphQLResponse::error(err.into_field_error()), &error_reporting))
+        }
+    };
+
     let post_loader = get_post_loader(pool.clone());
-    let context = Context { pool, hashing, post_loader };
+    let user_loader = get_user_loader(pool.clone());
+    let comment_loader = get_comment_loader(pool.clone());
+    let tag_loader = get_tag_loader(pool.clone());
+    let context = Context {
+        pool,
+        hashing,
+        tokens,
+        markdown,
+        mailer,
+        site,
+        policy: policy.get_ref().clone(),
+        current_user,
+        auth_error,
+        identified_user: Arc::new(Mutex::new(None)),
+        post_loader,
+        user_loader,
+        comment_loader,
+        tag_loader
+    };
+    let GraphQLBody { query, operation_name, variables } = body.into_inner();
+    let operation = operation_label(&schema, &query, operation_name.as_deref());
+    let data = GraphQLRequest::new(query, operation_name, variables);
+    let timer = GRAPHQL_DURATION.with_label_values(&[&operation]).start_timer();
     let res = data.execute(&schema, &context).await;
+    timer.observe_duration();
+
+    HttpResponse::Ok().json(graphql_response(res, &error_reporting))
+}
+
+/// Root field resolved by the operation, e.g. `posts` or `createPost`
+/// Only fields of the schema are used so the label set stays bounded; operations selecting
+/// several root fields are grouped as `multiple`, anything else as `other`
+fn operation_label(schema: &Schema, query: &str, operation_name: Option<&str>) -> String {
+    let (kind, fields) = match root_fields(query, operation_name) {
+        Some(operation) => operation,
+        None => return "other".to_string()
+    };
+
+    let root_type = match kind {
+        "query" => Some(schema.schema.concrete_query_type()),
+        "mutation" => schema.schema.concrete_mutation_type(),
+        _ => None
+    };
+
+    match root_type {
+        Some(_) if fields.len() > 1 => "multiple".to_string(),
+        Some(root_type) if fields.len() == 1 && root_type.field_by_name(&fields[0]).is_some() => fields[0].clone(),
+        _ => "other".to_string()
+    }
+}
+
+/// Serialized response, with error causes only in debug mode
+fn graphql_response(response: GraphQLResponse, error_reporting: &ErrorReporting) -> serde_json::Value {
+    let mut value = serde_json::to_value(response).unwrap_or_default();
+
+    if !error_reporting.debug {
+        hide_causes(&mut value);
+    }
 
-    HttpResponse::Ok().json(res)
+    value
 }
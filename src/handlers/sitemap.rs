@@ -0,0 +1,146 @@
+/// XML sitemap of published posts and user profiles
+/// https://www.sitemaps.org/protocol.html
+
+use actix_web::{web, HttpResponse};
+use deadpool_postgres::Pool;
+use super::feeds::escape;
+use crate::{
+    config::Site,
+    errors::{AppError, AppErrorType},
+    repositories::sitemap::{SitemapEntry, SitemapRepository}
+};
+
+/// Protocol limit of URLs per sitemap file
+pub const MAX_URLS: i64 = 50_000;
+
+const CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+const LASTMOD_FORMAT: &str = "%Y-%m-%dT%H:%M:%S+00:00";
+
+pub fn urlset(site: &Site, entries: &[SitemapEntry]) -> String {
+    let mut xml = String::new();
+
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+
+    for entry in entries {
+        let (loc, updated_at) = match entry {
+            SitemapEntry::Post { slug, updated_at } => (site.post_url(slug), updated_at),
+            SitemapEntry::User { username, updated_at } => (site.user_url(username), updated_at),
+        };
+
+        xml.push_str("<url>");
+        xml.push_str(&format!("<loc>{}</loc>", escape(&loc)));
+        xml.push_str(&format!("<lastmod>{}</lastmod>", updated_at.format(LASTMOD_FORMAT)));
+        xml.push_str("</url>");
+    }
+
+    xml.push_str("</urlset>");
+    xml
+}
+
+pub fn index(site: &Site, pages: i64) -> String {
+    let mut xml = String::new();
+
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+
+    for page in 1..=pages {
+        xml.push_str(&format!("<sitemap><loc>{}</loc></sitemap>", escape(&sitemap_url(site, page))));
+    }
+
+    xml.push_str("</sitemapindex>");
+    xml
+}
+
+fn sitemap_url(site: &Site, page: i64) -> String {
+    format!("{}/sitemaps/{}.xml", site.url, page)
+}
+
+fn pages(count: i64) -> i64 {
+    (count + MAX_URLS - 1) / MAX_URLS
+}
+
+fn xml_response(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .body(body)
+}
+
+/// The full sitemap, or a sitemap index once there are more than `MAX_URLS` entries
+pub async fn sitemap(pool: web::Data<Pool>, site: web::Data<Site>) -> Result<HttpResponse, AppError> {
+    let repository = SitemapRepository::new(pool.into_inner());
+    let count = repository.count().await?;
+
+    if count > MAX_URLS {
+        return Ok(xml_response(index(&site, pages(count))));
+    }
+
+    let entries = repository.entries(MAX_URLS, 0).await?;
+
+    Ok(xml_response(urlset(&site, &entries)))
+}
+
+/// One of the sitemaps listed in the index, starting at 1
+pub async fn sitemap_page(page: web::Path<i64>, pool: web::Data<Pool>, site: web::Data<Site>) -> Result<HttpResponse, AppError> {
+    let page = page.into_inner();
+    let repository = SitemapRepository::new(pool.into_inner());
+    let count = repository.count().await?;
+
+    if page < 1 || page > pages(count) {
+        return Err(AppError {
+            message: Some(format!("Sitemap {} not found", page)),
+            cause: None,
+            error_type: AppErrorType::NotFoundError,
+            field: None
+        });
+    }
+
+    let entries = repository.entries(MAX_URLS, (page - 1) * MAX_URLS).await?;
+
+    Ok(xml_response(urlset(&site, &entries)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{index, pages, urlset, MAX_URLS};
+    use crate::{config::Site, repositories::sitemap::SitemapEntry};
+    use chrono::{TimeZone, Utc};
+
+    fn site() -> Site {
+        Site {
+            url: "http://127.0.0.1:8080".to_string(),
+            title: "Blog".to_string(),
+            description: "Blog".to_string()
+        }
+    }
+
+    #[test]
+    fn test_urlset() {
+        let updated_at = Utc.ymd(2020, 3, 14).and_hms(5, 56, 36);
+        let entries = vec![
+            SitemapEntry::Post { slug: "hello-world".to_string(), updated_at },
+            SitemapEntry::User { username: "user1".to_string(), updated_at },
+        ];
+
+        let xml = urlset(&site(), &entries);
+
+        assert!(xml.contains("<loc>http://127.0.0.1:8080/posts/hello-world</loc>"), "Posts should be listed by slug");
+        assert!(xml.contains("<loc>http://127.0.0.1:8080/users/user1</loc>"), "User profiles should be listed");
+        assert!(xml.contains("<lastmod>2020-03-14T05:56:36+00:00</lastmod>"), "lastmod should be W3C datetime");
+    }
+
+    #[test]
+    fn test_index() {
+        let xml = index(&site(), 2);
+
+        assert!(xml.contains("<loc>http://127.0.0.1:8080/sitemaps/1.xml</loc>"), "First sitemap should be listed");
+        assert!(xml.contains("<loc>http://127.0.0.1:8080/sitemaps/2.xml</loc>"), "Second sitemap should be listed");
+    }
+
+    #[test]
+    fn test_pages() {
+        assert_eq!(pages(MAX_URLS), 1, "A full sitemap fits in one page");
+        assert_eq!(pages(MAX_URLS + 1), 2, "One more URL needs another page");
+    }
+}
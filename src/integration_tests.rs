@@ -2,23 +2,24 @@
 
 use crate::config::Config;
 use crate::handlers::app_config;
-use actix_web::{test, App};
+use crate::markdown::{MarkdownRenderer, DEFAULT_CACHE_SIZE};
+use actix_web::{http::header, test, web, App};
 use deadpool_postgres::Pool;
 use lazy_static::lazy_static;
 
 /// Holds the configuration and connection pool for tests
 struct TestConfig {
-    _config: Config,
+    config: Config,
     pool: Pool,
 }
 
 lazy_static! {
     static ref CONFIG: TestConfig = {
-        let _config = Config::from_env().unwrap();
+        let config = Config::from_env().unwrap();
 
-        let pool = _config.configure_pool();
+        let pool = config.configure_pool();
 
-        TestConfig { _config, pool }
+        TestConfig { config, pool }
     };
 }
 
@@ -34,3 +35,88 @@ async fn test_health() {
 
     assert_eq!(res.status(), 200, "GET / should return 200");
 }
+
+#[actix_rt::test]
+async fn test_ready() {
+    let app = App::new().data(CONFIG.pool.clone()).configure(app_config);
+
+    let mut app = test::init_service(app).await;
+
+    let req = test::TestRequest::get().uri("/ready").to_request();
+
+    let res = test::call_service(&mut app, req).await;
+
+    assert_eq!(res.status(), 200, "GET /ready should return 200 when the database is reachable");
+}
+
+#[actix_rt::test]
+async fn test_feed_not_modified() {
+    let app = App::new()
+        .data(CONFIG.pool.clone())
+        .data(CONFIG.config.site())
+        .configure(app_config);
+
+    let mut app = test::init_service(app).await;
+
+    let req = test::TestRequest::get().uri("/feed.xml").to_request();
+
+    let res = test::call_service(&mut app, req).await;
+
+    assert_eq!(res.status(), 200, "GET /feed.xml should return 200");
+
+    let etag = res.headers().get(header::ETAG).expect("ETag should be set").clone();
+
+    let req = test::TestRequest::get()
+        .uri("/feed.xml")
+        .header(header::IF_NONE_MATCH, etag)
+        .to_request();
+
+    let res = test::call_service(&mut app, req).await;
+
+    assert_eq!(res.status(), 304, "GET /feed.xml with a matching ETag should return 304");
+}
+
+#[actix_rt::test]
+async fn test_login_returns_own_email() {
+    let app = App::new()
+        .data(CONFIG.pool.clone())
+        .data(CONFIG.config.hashing_service())
+        .data(CONFIG.config.token_service())
+        .data(CONFIG.config.site())
+        .data(CONFIG.config.mailer().unwrap())
+        .data(CONFIG.config.auth_policy())
+        .data(CONFIG.config.error_reporting())
+        .app_data(web::Data::new(MarkdownRenderer::new(DEFAULT_CACHE_SIZE)))
+        .configure(app_config);
+
+    let mut app = test::init_service(app).await;
+
+    let username = format!("user{}", &uuid::Uuid::new_v4().to_simple().to_string()[..8]);
+    let email = format!("{}@example.com", username);
+
+    let req = test::TestRequest::post()
+        .uri("/graphql")
+        .set_json(&serde_json::json!({
+            "query": "mutation ($username: String!, $email: String!) { \
+                createUser(input: { username: $username, email: $email, password: \"password1\" }) { email } \
+            }",
+            "variables": { "username": username, "email": email }
+        }))
+        .to_request();
+
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+    assert_eq!(res["data"]["createUser"]["email"], email.as_str(), "createUser should return the new user's email");
+
+    let req = test::TestRequest::post()
+        .uri("/graphql")
+        .set_json(&serde_json::json!({
+            "query": "mutation ($login: String!) { login(usernameOrEmail: $login, password: \"password1\") { user { email } } }",
+            "variables": { "login": username }
+        }))
+        .to_request();
+
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+    assert_eq!(res["data"]["login"]["user"]["email"], email.as_str(), "login should return the caller's own email");
+}
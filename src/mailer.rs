@@ -0,0 +1,105 @@
+/// Outgoing email, behind a trait so the delivery mechanism can be swapped
+/// `stdout` and `file` transports are meant for local development
+
+use actix_web::web;
+use async_trait::async_trait;
+use chrono::Utc;
+use slog_scope::{error, info};
+use std::{fs, path::PathBuf};
+use crate::errors::{AppError, AppErrorType};
+
+#[derive(Clone, Debug)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    fn to_message(&self) -> String {
+        format!("To: {}\r\nSubject: {}\r\n\r\n{}\r\n", self.to, self.subject, self.body)
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AppError>;
+}
+
+/// Prints emails to the standard output
+pub struct StdoutMailer;
+
+#[async_trait]
+impl Mailer for StdoutMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        println!("{}", email.to_message());
+        Ok(())
+    }
+}
+
+/// Writes each email as an `.eml` file in `dir`
+pub struct FileMailer {
+    pub dir: PathBuf,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%d%H%M%S%6f"),
+            email.to.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        ));
+
+        let dir = self.dir.clone();
+        let file = path.clone();
+        let message = email.to_message();
+
+        // Blocking file IO runs on the actix thread pool
+        web::block(move || fs::create_dir_all(&dir).and_then(|_| fs::write(&file, message)))
+            .await
+            .map_err(|err| {
+                error!("Error writing email to {:?}. {}", path, err);
+                AppError {
+                    message: Some("Unable to send email".to_string()),
+                    cause: Some(err.to_string()),
+                    error_type: AppErrorType::MailError,
+                    field: None
+                }
+            })?;
+
+        info!("Email to {} written to {:?}", email.to, path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Email, FileMailer, Mailer};
+    use std::fs;
+
+    #[actix_rt::test]
+    async fn test_file_mailer() {
+        let dir = std::env::temp_dir().join(format!("actix-blog-app-mail-{}", uuid::Uuid::new_v4()));
+        let mailer = FileMailer { dir: dir.clone() };
+
+        mailer.send(Email {
+            to: "user1@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "World".to_string()
+        }).await.unwrap();
+
+        let files = fs::read_dir(&dir).unwrap().collect::<Vec<_>>();
+
+        assert_eq!(files.len(), 1, "One file should be written per email");
+
+        let message = fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+
+        assert!(message.contains("To: user1@example.com"), "Recipient should be included");
+        assert!(message.contains("World"), "Body should be included");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
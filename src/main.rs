@@ -1,21 +1,78 @@
 mod config;
 mod errors;
 mod handlers;
+mod mailer;
+mod markdown;
+mod metrics;
+mod migrations;
 mod models;
+mod one_time_token;
 mod repositories;
 
 use crate::config::Config;
+use crate::errors::AppError;
 use crate::handlers::app_config;
+use crate::markdown::{MarkdownRenderer, DEFAULT_CACHE_SIZE};
+use crate::metrics::Metrics;
+use crate::migrations::MigrationRunner;
 use actix_cors::Cors;
-use actix_web::{http::header, http::Method, middleware, App, HttpServer};
+use actix_web::{http::header, http::Method, middleware, web, App, HttpServer};
+use deadpool_postgres::Pool;
 use slog_scope::info;
+use std::{io, sync::Arc};
+
+/// `migrate run|revert|list`
+async fn migrate(pool: Pool, command: Option<&str>) -> io::Result<()> {
+    let runner = MigrationRunner::new(Arc::new(pool));
+    let to_io_error = |err: AppError| io::Error::new(io::ErrorKind::Other, format!("{} {:?}", err, err.cause));
+
+    match command {
+        Some("run") => {
+            let ran = runner.run().await.map_err(to_io_error)?;
+            println!("Applied {} migration(s)", ran.len());
+        },
+        Some("revert") => {
+            let migration = runner.revert().await.map_err(to_io_error)?;
+            println!("Reverted {}", migration.name);
+        },
+        Some("list") => {
+            for (migration, applied) in runner.list().await.map_err(to_io_error)? {
+                println!("[{}] {}", if applied { "X" } else { " " }, migration.name);
+            }
+        },
+        _ => return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: actix-blog-app migrate <run|revert|list>"
+        ))
+    }
+
+    Ok(())
+}
 
 #[actix_rt::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> io::Result<()> {
     let config = Config::from_env().unwrap();
 
     let pool = config.configure_pool();
+
+    let args = std::env::args().collect::<Vec<_>>();
+
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        return migrate(pool, args.get(2).map(String::as_str)).await;
+    }
+
+    if config.migrations.run_on_startup {
+        migrate(pool.clone(), Some("run")).await?;
+    }
+
     let hashing = config.hashing_service();
+    let tokens = config.token_service();
+    let site = config.site();
+    let mailer = config.mailer().unwrap();
+    let policy = config.auth_policy();
+    let error_reporting = config.error_reporting();
+    // Shared by all workers
+    let markdown = web::Data::new(MarkdownRenderer::new(DEFAULT_CACHE_SIZE));
 
     let host = config.server.host;
     let port = config.server.port;
@@ -36,7 +93,14 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(cors)
             .wrap(middleware::Logger::default())
+            .wrap(Metrics)
             .data(hashing.clone())
+            .data(tokens.clone())
+            .data(site.clone())
+            .data(mailer.clone())
+            .data(policy.clone())
+            .data(error_reporting.clone())
+            .app_data(markdown.clone())
             .data(pool.clone())
             .configure(app_config)
     })
@@ -0,0 +1,118 @@
+/// Markdown rendering for posts
+/// CommonMark (plus tables and strikethrough) rendered to sanitized HTML
+
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use pulldown_cmark::{html, Options, Parser};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+pub const DEFAULT_CACHE_SIZE: usize = 1000;
+
+/// Renders markdown to HTML, stripping anything unsafe (scripts, event handlers, etc.)
+pub fn render(source: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+
+    let parser = Parser::new_ext(source, options);
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser);
+
+    ammonia::clean(&unsafe_html)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Body,
+    Description,
+}
+
+/// A rendering is valid until the post is updated
+type CacheKey = (Uuid, Field, DateTime<Utc>);
+
+/// Keeps the most recently rendered posts in memory, shared by all workers
+pub struct MarkdownRenderer {
+    cache: Mutex<LruCache<CacheKey, String>>,
+}
+
+impl MarkdownRenderer {
+    pub fn new(capacity: usize) -> MarkdownRenderer {
+        MarkdownRenderer {
+            cache: Mutex::new(LruCache::new(capacity))
+        }
+    }
+
+    pub fn render(&self, id: Uuid, field: Field, updated_at: DateTime<Utc>, source: &str) -> String {
+        let key = (id, field, updated_at);
+
+        if let Some(html) = self.cache.lock().unwrap().get(&key) {
+            return html.clone();
+        }
+
+        let html = render(source);
+
+        self.cache.lock().unwrap().put(key, html.clone());
+
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{render, Field, MarkdownRenderer};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_render_commonmark() {
+        assert_eq!(render("# Title\n\n*hi*"), "<h1>Title</h1>\n<p><em>hi</em></p>\n", "Markdown should be rendered");
+    }
+
+    #[test]
+    fn test_render_table() {
+        let html = render("| a | b |\n|---|---|\n| 1 | 2 |\n");
+
+        assert!(html.contains("<table>"), "Tables should be rendered");
+        assert!(html.contains("<td>1</td>"), "Table cells should be rendered");
+    }
+
+    #[test]
+    fn test_render_fenced_code() {
+        let html = render("```rust\nfn main() {}\n```\n");
+
+        assert!(html.contains("<pre><code"), "Fenced code should be rendered");
+        assert!(html.contains("fn main() {}"), "Code should be kept");
+    }
+
+    #[test]
+    fn test_render_strips_unsafe_html() {
+        let html = render("<script>alert(1)</script>\n\n<a href=\"javascript:alert(1)\" onclick=\"x()\">link</a>");
+
+        assert!(!html.contains("<script"), "Scripts should be removed");
+        assert!(!html.contains("javascript:"), "Unsafe links should be removed");
+        assert!(!html.contains("onclick"), "Event handlers should be removed");
+    }
+
+    #[test]
+    fn test_cache_keyed_by_updated_at() {
+        let renderer = MarkdownRenderer::new(10);
+        let id = Uuid::new_v4();
+        let created = Utc.ymd(2020, 3, 14).and_hms(5, 56, 36);
+        let updated = Utc.ymd(2020, 3, 15).and_hms(5, 56, 36);
+
+        assert_eq!(renderer.render(id, Field::Body, created, "*old*"), "<p><em>old</em></p>\n");
+        assert_eq!(
+            renderer.render(id, Field::Body, created, "*new*"),
+            "<p><em>old</em></p>\n",
+            "Cached output should be reused for the same version"
+        );
+        assert_eq!(
+            renderer.render(id, Field::Body, updated, "*new*"),
+            "<p><em>new</em></p>\n",
+            "A new version should be rendered again"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+/// Prometheus metrics, exposed at `/metrics`
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpRequest
+};
+use futures::future::{ok, Ready};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, Histogram, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant
+};
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests by method, route and status",
+        &["method", "route", "status"]
+    ).unwrap();
+
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency by method and route",
+        &["method", "route"]
+    ).unwrap();
+
+    pub static ref GRAPHQL_DURATION: HistogramVec = register_histogram_vec!(
+        "graphql_operation_duration_seconds",
+        "Time spent resolving GraphQL operations by root field",
+        &["operation"]
+    ).unwrap();
+
+    pub static ref POST_BATCH_SIZE: Histogram = register_histogram!(
+        "post_batch_size",
+        "Number of keys loaded per PostBatcher batch",
+        vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0]
+    ).unwrap();
+
+    pub static ref PASSWORD_HASH_DURATION: Histogram = register_histogram!(
+        "password_hash_duration_seconds",
+        "Time spent hashing passwords",
+        vec![0.05, 0.1, 0.2, 0.3, 0.5, 0.75, 1.0, 2.0]
+    ).unwrap();
+
+    pub static ref DB_POOL: IntGaugeVec = register_int_gauge_vec!(
+        "db_pool_connections",
+        "Postgres pool connections by state",
+        &["state"]
+    ).unwrap();
+}
+
+/// Metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = vec![];
+
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+
+    String::from_utf8(buffer).unwrap()
+}
+
+/// Route pattern of the matched resource, e.g. `/users/{username}/feed.xml`
+/// Paths that didn't match any resource are grouped together to keep the label set bounded
+pub fn route_label(req: &HttpRequest) -> String {
+    req.match_pattern().unwrap_or_else(|| "unmatched".to_string())
+}
+
+/// Operation type and root field names (not aliases) of the operation that will be executed,
+/// the one named `operation_name` or else the first one. A light scan, not a full parser:
+/// fields selected through fragments at the root are not reported.
+pub fn root_fields(query: &str, operation_name: Option<&str>) -> Option<(&'static str, Vec<String>)> {
+    let chars = query.chars().collect::<Vec<_>>();
+    let mut operations: Vec<(&'static str, Option<String>, Vec<String>)> = vec![];
+    let mut keyword: Option<&'static str> = None;
+    let mut name: Option<String> = None;
+    let mut braces = 0;
+    let mut parens = 0;
+    // The next word is not a field: after `...`, `@`, or a field name expecting its alias target
+    let mut skip_word = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word = chars[start..i].iter().collect::<String>();
+            // Inline fragments: `... on Type`
+            let skip_next = skip_word && word == "on";
+
+            if braces == 0 && parens == 0 {
+                match (keyword, word.as_str()) {
+                    (None, "query") => keyword = Some("query"),
+                    (None, "mutation") => keyword = Some("mutation"),
+                    (None, "subscription") => keyword = Some("subscription"),
+                    (None, "fragment") => keyword = Some("fragment"),
+                    (Some(_), _) if name.is_none() => name = Some(word),
+                    _ => {}
+                }
+            } else if braces == 1 && parens == 0 && !skip_word {
+                let is_alias = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&':');
+
+                if !is_alias {
+                    if let Some((_, _, fields)) = operations.last_mut() {
+                        fields.push(word);
+                    }
+                }
+            }
+
+            skip_word = skip_next;
+            continue;
+        }
+
+        match c {
+            '#' => while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                i += 1;
+            },
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            },
+            '.' | '@' => skip_word = true,
+            '{' => {
+                if braces == 0 && parens == 0 {
+                    let kind = keyword.take().unwrap_or("query");
+                    operations.push((kind, name.take(), vec![]));
+                }
+                braces += 1;
+            },
+            '}' => braces -= 1,
+            '(' | '[' => parens += 1,
+            ')' | ']' => parens -= 1,
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    operations
+        .into_iter()
+        .filter(|(kind, _, _)| *kind != "fragment")
+        .find(|(_, name, _)| operation_name.is_none() || name.as_deref() == operation_name)
+        .map(|(kind, _, fields)| (kind, fields))
+}
+
+/// Middleware recording request counts and latency
+pub struct Metrics;
+
+impl<S, B> Transform<S> for Metrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware { service })
+    }
+}
+
+pub struct MetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let status = res.status();
+            let route = route_label(res.request());
+
+            HTTP_REQUESTS
+                .with_label_values(&[&method, &route, status.as_str()])
+                .inc();
+            HTTP_REQUEST_DURATION
+                .with_label_values(&[&method, &route])
+                .observe(start.elapsed().as_secs_f64());
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{root_fields, route_label};
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+
+    #[actix_rt::test]
+    async fn test_route_label() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/users/{username}/feed.xml")
+                    .to(|req: HttpRequest| async move { HttpResponse::Ok().body(route_label(&req)) })
+            )
+        ).await;
+
+        let req = test::TestRequest::with_uri("/users/user1/feed.xml").to_request();
+        let body = test::read_response(&mut app, req).await;
+
+        assert_eq!(body, &b"/users/{username}/feed.xml"[..], "Path parameters should be replaced by their names");
+    }
+
+    #[test]
+    fn test_unmatched_route_label() {
+        let req = test::TestRequest::with_uri("/wp-admin").to_http_request();
+
+        assert_eq!(route_label(&req), "unmatched", "Unknown paths should not become labels");
+    }
+
+    #[test]
+    fn test_root_fields() {
+        assert_eq!(
+            root_fields("{ posts { title } }", None),
+            Some(("query", vec!["posts".to_string()])),
+            "Shorthand is a query"
+        );
+
+        let document = r#"
+            # query in a comment
+            query Posts($q: String = "mutation {") { latest: posts(q: $q) { ...PostFields } }
+            fragment PostFields on Post { title }
+            mutation CreatePost { createPost(input: { title: "x" }) @include(if: true) { title } }
+        "#;
+
+        assert_eq!(
+            root_fields(document, Some("CreatePost")),
+            Some(("mutation", vec!["createPost".to_string()])),
+            "Named operation is selected"
+        );
+        assert_eq!(
+            root_fields(document, None),
+            Some(("query", vec!["posts".to_string()])),
+            "First operation is used without a name, aliases are skipped"
+        );
+        assert_eq!(root_fields("not graphql", None), None, "Documents without operations have no fields");
+    }
+}
@@ -0,0 +1,196 @@
+/// Embedded SQL migrations from `migrations/`, applied without diesel_cli
+/// Applied versions are tracked in `__diesel_schema_migrations` so both tools can be used on the same database
+
+use deadpool_postgres::{Client, Pool};
+use slog_scope::{error, info};
+use std::{collections::HashSet, sync::Arc};
+use crate::errors::{AppError, AppErrorType};
+
+pub struct Migration {
+    /// Directory name prefix without dashes, e.g. `20200314055636`
+    pub version: &'static str,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+/// Advisory lock key ("migrate" in ASCII) serializing runners across processes,
+/// e.g. the release step and web instances migrating on startup
+const MIGRATIONS_LOCK: i64 = 0x6d69_6772_6174_65;
+
+pub struct MigrationRunner {
+    pool: Arc<Pool>,
+}
+
+impl MigrationRunner {
+
+    pub fn new(pool: Arc<Pool>) -> MigrationRunner {
+        MigrationRunner { pool }
+    }
+
+    async fn client(&self) -> Result<Client, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "migrations");
+                err
+            })?;
+
+        client
+            .batch_execute(&format!(
+                "begin; \
+                select pg_advisory_xact_lock({}); \
+                create table if not exists __diesel_schema_migrations (\
+                    version varchar(50) primary key not null, \
+                    run_on timestamp not null default current_timestamp\
+                ); \
+                commit;",
+                MIGRATIONS_LOCK
+            ))
+            .await
+            .map_err(|err| {
+                error!("Error creating migrations table. {}", err; "query" => "migrations");
+                err
+            })?;
+
+        Ok(client)
+    }
+
+    async fn applied_versions(&self, client: &Client) -> Result<HashSet<String>, AppError> {
+        let versions = client
+            .query("select version from __diesel_schema_migrations", &[])
+            .await
+            .map_err(|err| {
+                error!("Error getting applied migrations. {}", err; "query" => "applied_versions");
+                err
+            })?
+            .iter()
+            .map(|row| row.get::<_, String>("version"))
+            .collect();
+
+        Ok(versions)
+    }
+
+    /// Migrations with whether they have been applied, oldest first
+    pub async fn list(&self) -> Result<Vec<(&'static Migration, bool)>, AppError> {
+        let client = self.client().await?;
+        let applied = self.applied_versions(&client).await?;
+
+        Ok(MIGRATIONS
+            .iter()
+            .map(|migration| (migration, applied.contains(migration.version)))
+            .collect())
+    }
+
+    /// Applies every pending migration, each one in its own transaction
+    /// Holding the advisory lock, so a migration applied meanwhile by another process is skipped
+    pub async fn run(&self) -> Result<Vec<&'static Migration>, AppError> {
+        let mut client = self.client().await?;
+        let applied = self.applied_versions(&client).await?;
+        let mut ran = vec![];
+
+        for migration in MIGRATIONS.iter().filter(|migration| !applied.contains(migration.version)) {
+            let transaction = client.transaction().await?;
+
+            transaction.execute("select pg_advisory_xact_lock($1)", &[&MIGRATIONS_LOCK]).await?;
+
+            let already_applied = !transaction
+                .query("select version from __diesel_schema_migrations where version = $1", &[&migration.version])
+                .await?
+                .is_empty();
+
+            if already_applied {
+                info!("Migration {} already applied", migration.name);
+                continue;
+            }
+
+            info!("Running migration {}", migration.name);
+
+            transaction
+                .batch_execute(migration.up)
+                .await
+                .map_err(|err| {
+                    error!("Error running migration {}. {}", migration.name, err; "query" => "run");
+                    err
+                })?;
+
+            transaction
+                .execute("insert into __diesel_schema_migrations (version) values ($1)", &[&migration.version])
+                .await?;
+
+            transaction.commit().await?;
+
+            ran.push(migration);
+        }
+
+        Ok(ran)
+    }
+
+    /// Reverts the most recently applied migration
+    pub async fn revert(&self) -> Result<&'static Migration, AppError> {
+        let mut client = self.client().await?;
+        let applied = self.applied_versions(&client).await?;
+
+        let migration = MIGRATIONS
+            .iter()
+            .rev()
+            .find(|migration| applied.contains(migration.version))
+            .ok_or(AppError {
+                message: Some("No migrations to revert".to_string()),
+                cause: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })?;
+
+        info!("Reverting migration {}", migration.name);
+
+        let transaction = client.transaction().await?;
+
+        transaction.execute("select pg_advisory_xact_lock($1)", &[&MIGRATIONS_LOCK]).await?;
+
+        let deleted = transaction
+            .execute("delete from __diesel_schema_migrations where version = $1", &[&migration.version])
+            .await?;
+
+        if deleted == 0 {
+            return Err(AppError {
+                message: Some(format!("Migration {} was reverted meanwhile", migration.name)),
+                cause: None,
+                error_type: AppErrorType::Conflict,
+                field: None
+            });
+        }
+
+        transaction
+            .batch_execute(migration.down)
+            .await
+            .map_err(|err| {
+                error!("Error reverting migration {}. {}", migration.name, err; "query" => "revert");
+                err
+            })?;
+
+        transaction.commit().await?;
+
+        Ok(migration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::MIGRATIONS;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        let versions = MIGRATIONS.iter().map(|migration| migration.version).collect::<Vec<_>>();
+        let mut sorted = versions.clone();
+        sorted.sort();
+
+        assert!(!versions.is_empty(), "Migrations should be embedded");
+        assert_eq!(versions, sorted, "Migrations should run oldest first");
+        assert_eq!(versions[0], "20200314055636", "Versions should match diesel's");
+    }
+}
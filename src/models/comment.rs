@@ -1,7 +1,10 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use tokio_pg_mapper_derive::PostgresMapper;
+use juniper::GraphQLInputObject;
+use crate::errors::AppError;
+use crate::models::validation::{Rule, Validate, Validator};
 
 #[derive(Clone, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "comments")]
@@ -10,6 +13,20 @@ pub struct Comment {
     pub author_id: Uuid,
     pub post_id: Uuid,
     pub body: String,
-    pub created_at: NaiveDateTime,
-    pub updated_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct CreateComment {
+    pub post_id: Uuid,
+    pub body: String,
+}
+
+impl Validate for CreateComment {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .field("body", &self.body, &[Rule::NotBlank, Rule::Length { min: 0, max: 10_000 }])
+            .finish()
+    }
 }
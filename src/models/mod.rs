@@ -1,3 +1,6 @@
 pub mod comment;
+pub mod pagination;
 pub mod post;
-pub mod user;
\ No newline at end of file
+pub mod tag;
+pub mod user;
+pub mod validation;
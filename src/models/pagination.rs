@@ -0,0 +1,331 @@
+/// Relay-style cursor pagination
+/// Pages are ordered newest first by `(created_at, id)`
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use uuid::Uuid;
+use juniper::GraphQLObject;
+use crate::errors::{AppError, AppErrorType};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+const CURSOR_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        base64::encode(format!("{}|{}", self.created_at.format(CURSOR_DATE_FORMAT), self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Cursor, AppError> {
+        let invalid_cursor = AppError {
+            message: Some(format!("Invalid cursor {}", cursor)),
+            cause: None,
+            error_type: AppErrorType::InvalidField,
+            field: None
+        };
+
+        let decoded = base64::decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(invalid_cursor.clone())?;
+
+        let mut parts = decoded.splitn(2, '|');
+
+        match (parts.next(), parts.next()) {
+            (Some(created_at), Some(id)) => {
+                // Encoded in UTC, without offset
+                let created_at = NaiveDateTime::parse_from_str(created_at, CURSOR_DATE_FORMAT)
+                    .map(|created_at| DateTime::from_utc(created_at, Utc))
+                    .map_err(|_| invalid_cursor.clone())?;
+                let id = Uuid::parse_str(id)
+                    .map_err(|_| invalid_cursor.clone())?;
+
+                Ok(Cursor { created_at, id })
+            },
+            _ => Err(invalid_cursor)
+        }
+    }
+}
+
+/// Cursor for results without a stable `(created_at, id)` order, like ranked search hits
+pub fn encode_offset(offset: i64) -> String {
+    base64::encode(format!("offset|{}", offset))
+}
+
+pub fn decode_offset(cursor: &str) -> Result<i64, AppError> {
+    base64::decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|decoded| {
+            let mut parts = decoded.splitn(2, '|');
+
+            match (parts.next(), parts.next()) {
+                (Some("offset"), Some(offset)) => offset.parse::<i64>().ok(),
+                _ => None
+            }
+        })
+        .filter(|offset| *offset >= 0)
+        .ok_or(AppError {
+            message: Some(format!("Invalid cursor {}", cursor)),
+            cause: None,
+            error_type: AppErrorType::InvalidField,
+            field: None
+        })
+}
+
+/// Connection arguments as received from GraphQL
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PageRequest {
+    pub first: Option<i32>,
+    pub after: Option<String>,
+    pub last: Option<i32>,
+    pub before: Option<String>,
+}
+
+/// Validated connection arguments
+pub struct PageQuery {
+    pub limit: i64,
+    /// `true` when paginating with `last`/`before`
+    pub backwards: bool,
+    pub after: Option<Cursor>,
+    pub before: Option<Cursor>,
+}
+
+impl PageRequest {
+    pub fn new(first: Option<i32>, after: Option<String>, last: Option<i32>, before: Option<String>) -> PageRequest {
+        PageRequest { first, after, last, before }
+    }
+
+    pub fn parse(&self) -> Result<PageQuery, AppError> {
+        let invalid_size = |name: &str| AppError {
+            message: Some(format!("`{}` must be between 0 and {}", name, MAX_PAGE_SIZE)),
+            cause: None,
+            error_type: AppErrorType::InvalidField,
+            field: Some(name.to_string())
+        };
+
+        let (limit, backwards) = match (self.first, self.last) {
+            (Some(_), Some(_)) => return Err(AppError {
+                message: Some("Passing both `first` and `last` is not supported".to_string()),
+                cause: None,
+                error_type: AppErrorType::InvalidField,
+                field: None
+            }),
+            (Some(first), None) => (first as i64, false),
+            (None, Some(last)) => (last as i64, true),
+            (None, None) => (DEFAULT_PAGE_SIZE, self.before.is_some() && self.after.is_none())
+        };
+
+        if limit < 0 || limit > MAX_PAGE_SIZE {
+            return Err(invalid_size(if backwards { "last" } else { "first" }));
+        }
+
+        Ok(PageQuery {
+            limit,
+            backwards,
+            after: self.after.as_deref().map(Cursor::decode).transpose().map_err(|err| err.with_field("after"))?,
+            before: self.before.as_deref().map(Cursor::decode).transpose().map_err(|err| err.with_field("before"))?,
+        })
+    }
+}
+
+/// Implemented by models that can be paginated by `(created_at, id)`
+pub trait Node {
+    fn cursor(&self) -> Cursor;
+}
+
+#[derive(Clone)]
+pub struct Edge<T> {
+    pub cursor: String,
+    pub node: T,
+}
+
+#[derive(Clone, GraphQLObject)]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub has_previous_page: bool,
+    pub start_cursor: Option<String>,
+    pub end_cursor: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Connection<T> {
+    pub edges: Vec<Edge<T>>,
+    pub page_info: PageInfo,
+    pub total_count: i64,
+}
+
+impl<T: Node> Connection<T> {
+    /// Builds the connection from rows fetched in query order,
+    /// which may include one row past `limit` to detect further pages
+    pub fn from_rows(mut nodes: Vec<T>, query: &PageQuery, total_count: i64) -> Connection<T> {
+        let has_more = nodes.len() as i64 > query.limit;
+
+        nodes.truncate(query.limit as usize);
+
+        if query.backwards {
+            nodes.reverse();
+        }
+
+        let edges = nodes
+            .into_iter()
+            .map(|node| Edge {
+                cursor: node.cursor().encode(),
+                node
+            })
+            .collect::<Vec<_>>();
+
+        let page_info = PageInfo {
+            has_next_page: if query.backwards { query.before.is_some() } else { has_more },
+            has_previous_page: if query.backwards { has_more } else { query.after.is_some() },
+            start_cursor: edges.first().map(|edge| edge.cursor.clone()),
+            end_cursor: edges.last().map(|edge| edge.cursor.clone()),
+        };
+
+        Connection {
+            edges,
+            page_info,
+            total_count
+        }
+    }
+}
+
+impl<T> Connection<T> {
+    /// Builds the connection for rows fetched with `offset` and `limit + 1`
+    pub fn from_offset(mut nodes: Vec<T>, offset: i64, limit: i64, total_count: i64) -> Connection<T> {
+        let has_more = nodes.len() as i64 > limit;
+
+        nodes.truncate(limit as usize);
+
+        let edges = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(index, node)| Edge {
+                cursor: encode_offset(offset + index as i64 + 1),
+                node
+            })
+            .collect::<Vec<_>>();
+
+        let page_info = PageInfo {
+            has_next_page: has_more,
+            has_previous_page: offset > 0,
+            start_cursor: edges.first().map(|edge| edge.cursor.clone()),
+            end_cursor: edges.last().map(|edge| edge.cursor.clone()),
+        };
+
+        Connection {
+            edges,
+            page_info,
+            total_count
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{decode_offset, encode_offset, Connection, Cursor, Node, PageRequest, DEFAULT_PAGE_SIZE};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    struct Item(Cursor);
+
+    impl Node for Item {
+        fn cursor(&self) -> Cursor {
+            self.0.clone()
+        }
+    }
+
+    fn cursor(second: u32) -> Cursor {
+        Cursor {
+            created_at: Utc.ymd(2020, 3, 14).and_hms_micro(5, 56, second, 123_456),
+            id: Uuid::new_v4()
+        }
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let cursor = cursor(36);
+
+        assert_eq!(
+            Cursor::decode(&cursor.encode()).unwrap(),
+            cursor,
+            "Decoded cursor should match the encoded one"
+        );
+    }
+
+    #[test]
+    fn test_invalid_cursor() {
+        assert!(Cursor::decode("not-a-cursor").is_err(), "Garbage cursor should be rejected");
+    }
+
+    #[test]
+    fn test_default_page_size() {
+        let query = PageRequest::default().parse().unwrap();
+
+        assert_eq!(query.limit, DEFAULT_PAGE_SIZE, "Default page size should be used");
+        assert!(!query.backwards, "Default pagination should be forward");
+    }
+
+    #[test]
+    fn test_first_and_last_rejected() {
+        let request = PageRequest::new(Some(1), None, Some(1), None);
+
+        assert!(request.parse().is_err(), "first and last together should be rejected");
+    }
+
+    #[test]
+    fn test_page_size_out_of_range() {
+        assert!(PageRequest::new(Some(-1), None, None, None).parse().is_err(), "Negative size should be rejected");
+        assert!(PageRequest::new(Some(1000), None, None, None).parse().is_err(), "Huge size should be rejected");
+    }
+
+    #[test]
+    fn test_forward_connection() {
+        let query = PageRequest::new(Some(2), None, None, None).parse().unwrap();
+        let items = vec![Item(cursor(3)), Item(cursor(2)), Item(cursor(1))];
+
+        let connection = Connection::from_rows(items, &query, 3);
+
+        assert_eq!(connection.edges.len(), 2, "Extra row should be dropped");
+        assert!(connection.page_info.has_next_page, "Extra row means there is a next page");
+        assert!(!connection.page_info.has_previous_page, "First page has no previous page");
+    }
+
+    #[test]
+    fn test_backward_connection() {
+        let before = cursor(0).encode();
+        let query = PageRequest::new(None, None, Some(2), Some(before)).parse().unwrap();
+        let items = vec![Item(cursor(1)), Item(cursor(2)), Item(cursor(3))];
+
+        let connection = Connection::from_rows(items, &query, 3);
+
+        assert_eq!(connection.edges.len(), 2, "Extra row should be dropped");
+        assert_eq!(connection.edges[0].node.0.created_at, cursor(2).created_at, "Edges should be in forward order");
+        assert!(connection.page_info.has_previous_page, "Extra row means there is a previous page");
+        assert!(connection.page_info.has_next_page, "Paginating before a cursor means there is a next page");
+    }
+
+    #[test]
+    fn test_offset_cursor() {
+        assert_eq!(decode_offset(&encode_offset(42)).unwrap(), 42, "Offset should roundtrip");
+        assert!(decode_offset(&encode_offset(-1)).is_err(), "Negative offsets should be rejected");
+        assert!(decode_offset(&cursor(1).encode()).is_err(), "Keyset cursors are not offsets");
+    }
+
+    #[test]
+    fn test_offset_connection() {
+        let connection = Connection::from_offset(vec![1, 2, 3], 10, 2, 20);
+
+        assert_eq!(connection.edges.len(), 2, "Extra row should be dropped");
+        assert_eq!(decode_offset(&connection.edges[1].cursor).unwrap(), 12, "Cursor points past the edge");
+        assert!(connection.page_info.has_next_page, "Extra row means there is a next page");
+        assert!(connection.page_info.has_previous_page, "Non-zero offset means there is a previous page");
+    }
+}
@@ -1,10 +1,32 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use tokio_pg_mapper_derive::PostgresMapper;
-use juniper::{GraphQLObject, GraphQLInputObject};
+use postgres_types::{FromSql, ToSql};
+use juniper::{GraphQLEnum, GraphQLInputObject};
+use crate::errors::AppError;
+use crate::models::{
+    pagination::{Cursor, Node},
+    tag::{MAX_TAGS, MAX_TAG_LENGTH},
+    validation::{Rule, Validate, Validator}
+};
+use deunicode::deunicode;
 
-#[derive(Clone, Serialize, Deserialize, PostgresMapper, GraphQLObject)]
+const MAX_SLUG_LENGTH: usize = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "post_status")]
+pub enum PostStatus {
+    #[postgres(name = "draft")]
+    Draft,
+    #[postgres(name = "published")]
+    Published,
+    /// Published once `published_at` is reached
+    #[postgres(name = "scheduled")]
+    Scheduled,
+}
+
+#[derive(Clone, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "posts")]
 pub struct Post {
     pub id: Uuid,
@@ -13,15 +35,164 @@ pub struct Post {
     pub title: String,
     pub description: String,
     pub body: String,
-    pub created_at: NaiveDateTime,
-    pub updated_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub status: PostStatus,
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+impl Post {
+    /// Whether the post is visible to everyone
+    pub fn is_published(&self) -> bool {
+        match (self.status, self.published_at) {
+            (PostStatus::Draft, _) | (_, None) => false,
+            (_, Some(published_at)) => published_at <= Utc::now()
+        }
+    }
+
+    /// Scheduled posts are reported as published once their time has passed
+    pub fn effective_status(&self) -> PostStatus {
+        match self.status {
+            PostStatus::Scheduled if self.is_published() => PostStatus::Published,
+            status => status
+        }
+    }
+}
+
+/// A post matching a full-text search
+#[derive(Clone)]
+pub struct SearchHit {
+    pub post: Post,
+    pub rank: f32,
+    /// Matching excerpt of the body, HTML-escaped with matches wrapped in `<mark>`
+    pub snippet: String,
+}
+
+impl Node for Post {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id
+        }
+    }
 }
 
 #[derive(GraphQLInputObject)]
 pub struct CreatePost {
-    pub author_id: Uuid,
     pub slug: Option<String>,
     pub title: String,
     pub description: String,
     pub body: String,
-}
\ No newline at end of file
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct UpdatePost {
+    pub slug: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub body: Option<String>,
+    /// Replaces the post's tags when provided
+    pub tags: Option<Vec<String>>,
+    /// `updatedAt` of the post being edited, the update fails if it changed since
+    pub expected_updated_at: Option<DateTime<Utc>>,
+}
+
+const MAX_TITLE_LENGTH: usize = 200;
+const MAX_DESCRIPTION_LENGTH: usize = 500;
+const MAX_BODY_LENGTH: usize = 100_000;
+
+impl Validate for CreatePost {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .optional("slug", self.slug.as_deref(), &[Rule::Length { min: 1, max: MAX_SLUG_LENGTH }, Rule::Slug])
+            .field("title", &self.title, &[Rule::NotBlank, Rule::Length { min: 0, max: MAX_TITLE_LENGTH }])
+            .field("description", &self.description, &[Rule::Length { min: 0, max: MAX_DESCRIPTION_LENGTH }])
+            .field("body", &self.body, &[Rule::NotBlank, Rule::Length { min: 0, max: MAX_BODY_LENGTH }])
+            .each("tags", self.tags.as_deref(), MAX_TAGS, &[Rule::Length { min: 0, max: MAX_TAG_LENGTH }])
+            .finish()
+    }
+}
+
+impl Validate for UpdatePost {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .optional("slug", self.slug.as_deref(), &[Rule::Length { min: 1, max: MAX_SLUG_LENGTH }, Rule::Slug])
+            .optional("title", self.title.as_deref(), &[Rule::NotBlank, Rule::Length { min: 0, max: MAX_TITLE_LENGTH }])
+            .optional("description", self.description.as_deref(), &[Rule::Length { min: 0, max: MAX_DESCRIPTION_LENGTH }])
+            .optional("body", self.body.as_deref(), &[Rule::NotBlank, Rule::Length { min: 0, max: MAX_BODY_LENGTH }])
+            .each("tags", self.tags.as_deref(), MAX_TAGS, &[Rule::Length { min: 0, max: MAX_TAG_LENGTH }])
+            .finish()
+    }
+}
+
+/// Builds a URL-safe slug from a title, transliterating non-ASCII characters
+/// e.g. "Ünïcödé & Rust!" -> "unicode-rust"
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+
+    for c in deunicode(title).chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.truncate(MAX_SLUG_LENGTH);
+
+    match slug.trim_end_matches('-') {
+        "" => "post".to_string(),
+        slug => slug.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{slugify, CreatePost};
+    use crate::errors::AppErrorType;
+    use crate::models::validation::Validate;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world", "Punctuation should be collapsed");
+        assert_eq!(slugify("  Rust   &   Actix  "), "rust-actix", "Dashes should not repeat or trail");
+    }
+
+    #[test]
+    fn test_slugify_transliterates() {
+        assert_eq!(slugify("Ünïcödé café"), "unicode-cafe", "Accents should be transliterated");
+        assert_eq!(slugify("北亰"), "bei-jing", "Non-latin scripts should be transliterated");
+    }
+
+    #[test]
+    fn test_slugify_empty() {
+        assert_eq!(slugify("!!!"), "post", "A fallback slug should be used");
+    }
+
+    #[test]
+    fn test_slugify_length() {
+        assert!(slugify(&"a".repeat(200)).len() <= 80, "Slug should be truncated");
+    }
+
+    #[test]
+    fn test_create_post_validation() {
+        let input = CreatePost {
+            slug: Some("Not a slug".to_string()),
+            title: " ".to_string(),
+            description: "".to_string(),
+            body: "Body".to_string(),
+            tags: None
+        };
+
+        match input.validate().unwrap_err().error_type {
+            AppErrorType::ValidationError(violations) => {
+                let fields = violations.iter().map(|violation| violation.field.as_str()).collect::<Vec<_>>();
+
+                assert_eq!(fields, vec!["slug", "title"], "Invalid slug and blank title should be reported");
+            },
+            _ => panic!("Validation error expected")
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use tokio_pg_mapper_derive::PostgresMapper;
+use juniper::GraphQLObject;
+
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_LENGTH: usize = 50;
+
+#[derive(Clone, Serialize, Deserialize, PostgresMapper, GraphQLObject)]
+#[pg_mapper(table = "tags")]
+pub struct Tag {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A tag along with the number of published posts using it
+#[derive(Clone)]
+pub struct TagCount {
+    pub tag: Tag,
+    pub post_count: i64,
+}
+
+/// Tags are stored lowercase, trimmed and without duplicates
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = vec![];
+
+    for tag in tags {
+        let tag = normalize_tag(tag);
+
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+
+    normalized
+}
+
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::normalize_tags;
+
+    #[test]
+    fn test_normalize_tags() {
+        let tags = vec![
+            " Rust ".to_string(),
+            "rust".to_string(),
+            "".to_string(),
+            "Actix".to_string()
+        ];
+
+        assert_eq!(
+            normalize_tags(&tags),
+            vec!["rust".to_string(), "actix".to_string()],
+            "Tags should be trimmed, lowercased and deduplicated"
+        );
+    }
+}
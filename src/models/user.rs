@@ -1,11 +1,35 @@
 /// User model
 /// Includes login and profile info
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use tokio_pg_mapper_derive::PostgresMapper;
-use juniper::GraphQLInputObject;
+use postgres_types::{FromSql, ToSql};
+use juniper::{GraphQLEnum, GraphQLInputObject};
+use crate::errors::AppError;
+use crate::models::{
+    pagination::{Cursor, Node},
+    validation::{Rule, Validate, Validator}
+};
+
+/// Ordered by privilege, each role can do everything the previous ones can
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "user_role")]
+pub enum Role {
+    /// Can comment
+    #[postgres(name = "reader")]
+    Reader,
+    /// Can write and edit their own posts
+    #[postgres(name = "author")]
+    Author,
+    /// Can edit and delete any post or comment
+    #[postgres(name = "editor")]
+    Editor,
+    /// Can manage users
+    #[postgres(name = "admin")]
+    Admin,
+}
 
 #[derive(Clone, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "users")]
@@ -16,8 +40,40 @@ pub struct User {
     pub password: String,
     pub bio: Option<String>,
     pub image: Option<String>,
-    pub created_at: NaiveDateTime,
-    pub updated_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub password_changed_at: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub role: Role,
+}
+
+impl User {
+    /// Whether the user has `role` or a more privileged one
+    pub fn has_role(&self, role: Role) -> bool {
+        self.role >= role
+    }
+
+    /// Access tokens issued before the last password change are revoked
+    /// Compared in whole seconds, the precision of the token's `iat`
+    pub fn accepts_token_issued_at(&self, issued_at: DateTime<Utc>) -> bool {
+        match self.password_changed_at {
+            Some(changed_at) => issued_at.timestamp() >= changed_at.timestamp(),
+            None => true
+        }
+    }
+
+    pub fn is_email_verified(&self) -> bool {
+        self.email_verified_at.is_some()
+    }
+}
+
+impl Node for User {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id
+        }
+    }
 }
 
 #[derive(GraphQLInputObject)]
@@ -27,4 +83,25 @@ pub struct CreateUser {
     pub password: String,
     pub bio: Option<String>,
     pub image: Option<String>
-}
\ No newline at end of file
+}
+
+pub const PASSWORD_RULES: &[Rule] = &[Rule::Length { min: 8, max: 128 }, Rule::Password];
+
+impl Validate for CreateUser {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .field("username", &self.username, &[Rule::Length { min: 3, max: 32 }, Rule::Username])
+            .field("email", &self.email, &[Rule::Length { min: 0, max: 254 }, Rule::Email])
+            .field("password", &self.password, PASSWORD_RULES)
+            .optional("bio", self.bio.as_deref(), &[Rule::Length { min: 0, max: 1000 }])
+            .optional("image", self.image.as_deref(), &[Rule::Length { min: 0, max: 2048 }, Rule::Url])
+            .finish()
+    }
+}
+
+/// Result of a successful login
+pub struct AuthPayload {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub user: User
+}
@@ -0,0 +1,199 @@
+/// Declarative input validation
+/// Every rule of every field is checked so all violations are reported at once
+
+pub use crate::errors::FieldViolation;
+use crate::errors::{AppError, AppErrorType};
+
+pub enum Rule {
+    /// At least one non-whitespace character
+    NotBlank,
+    /// Length in characters, inclusive
+    Length { min: usize, max: usize },
+    Email,
+    /// Letters, digits, `_` and `-`
+    Username,
+    /// At least one letter and one digit
+    Password,
+    /// Absolute http(s) URL
+    Url,
+    /// Lowercase letters, digits and `-`
+    Slug,
+}
+
+impl Rule {
+    /// The violation message, if `value` breaks the rule
+    fn check(&self, value: &str) -> Option<String> {
+        let valid = match self {
+            Rule::NotBlank => !value.trim().is_empty(),
+            Rule::Length { min, max } => {
+                let length = value.chars().count();
+                length >= *min && length <= *max
+            },
+            Rule::Email => is_email(value),
+            Rule::Username => value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            Rule::Password => value.chars().any(char::is_alphabetic) && value.chars().any(|c| c.is_ascii_digit()),
+            Rule::Url => is_url(value),
+            Rule::Slug => value.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+        };
+
+        if valid {
+            return None;
+        }
+
+        Some(match self {
+            Rule::NotBlank => "must not be blank".to_string(),
+            Rule::Length { min: 0, max } => format!("must be at most {} characters", max),
+            Rule::Length { min, max } => format!("must be between {} and {} characters", min, max),
+            Rule::Email => "must be a valid email address".to_string(),
+            Rule::Username => "may only contain letters, digits, `_` and `-`".to_string(),
+            Rule::Password => "must contain at least one letter and one digit".to_string(),
+            Rule::Url => "must be an http or https URL".to_string(),
+            Rule::Slug => "may only contain lowercase letters, digits and `-`".to_string(),
+        })
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    let mut parts = value.rsplitn(2, '@');
+
+    match (parts.next(), parts.next()) {
+        (Some(domain), Some(local)) => {
+            !local.is_empty()
+                && !value.chars().any(char::is_whitespace)
+                && domain.contains('.')
+                && domain.split('.').all(|label| !label.is_empty())
+        },
+        _ => false
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    let rest = if value.starts_with("https://") {
+        &value["https://".len()..]
+    } else if value.starts_with("http://") {
+        &value["http://".len()..]
+    } else {
+        return false;
+    };
+
+    let host = rest.split(|c| c == '/' || c == '?' || c == '#').next().unwrap_or("");
+
+    !host.is_empty() && !value.chars().any(char::is_whitespace)
+}
+
+/// Collects the violations of an input object
+#[derive(Default)]
+pub struct Validator {
+    violations: Vec<FieldViolation>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    pub fn field(mut self, field: &str, value: &str, rules: &[Rule]) -> Validator {
+        for rule in rules {
+            if let Some(message) = rule.check(value) {
+                self.violations.push(FieldViolation {
+                    field: field.to_string(),
+                    message
+                });
+            }
+        }
+
+        self
+    }
+
+    /// Rules only apply when the value is provided
+    pub fn optional(self, field: &str, value: Option<&str>, rules: &[Rule]) -> Validator {
+        match value {
+            Some(value) => self.field(field, value, rules),
+            None => self
+        }
+    }
+
+    /// Rules apply to each item of a list of at most `max_items`
+    pub fn each(mut self, field: &str, values: Option<&[String]>, max_items: usize, rules: &[Rule]) -> Validator {
+        let values = values.unwrap_or(&[]);
+
+        if values.len() > max_items {
+            self.violations.push(FieldViolation {
+                field: field.to_string(),
+                message: format!("must have at most {} items", max_items)
+            });
+        }
+
+        values
+            .iter()
+            .fold(self, |validator, value| validator.field(field, value, rules))
+    }
+
+    pub fn finish(self) -> Result<(), AppError> {
+        if self.violations.is_empty() {
+            return Ok(());
+        }
+
+        Err(AppError {
+            message: None,
+            cause: None,
+            error_type: AppErrorType::ValidationError(self.violations),
+            field: None
+        })
+    }
+}
+
+/// Implemented by input objects, checked before touching the database
+pub trait Validate {
+    fn validate(&self) -> Result<(), AppError>;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Rule, Validator};
+    use crate::errors::AppErrorType;
+
+    #[test]
+    fn test_rules() {
+        assert!(Rule::NotBlank.check("  ").is_some(), "Whitespace is blank");
+        assert!(Rule::Length { min: 3, max: 5 }.check("äöü").is_none(), "Length should count characters");
+        assert!(Rule::Email.check("user1@example.com").is_none(), "Valid email should pass");
+        assert!(Rule::Email.check("user1@localhost").is_some(), "Domain should have a dot");
+        assert!(Rule::Email.check("user 1@example.com").is_some(), "Email should not contain spaces");
+        assert!(Rule::Username.check("user_1-a").is_none(), "Valid username should pass");
+        assert!(Rule::Username.check("user@1").is_some(), "Username should not contain symbols");
+        assert!(Rule::Password.check("password").is_some(), "Password should contain a digit");
+        assert!(Rule::Url.check("https://example.com/a.png").is_none(), "Valid URL should pass");
+        assert!(Rule::Url.check("javascript:alert(1)").is_some(), "Only http(s) URLs are allowed");
+        assert!(Rule::Slug.check("Hello World").is_some(), "Slug should be lowercase without spaces");
+    }
+
+    #[test]
+    fn test_all_violations_reported() {
+        let result = Validator::new()
+            .field("username", "a!", &[Rule::Length { min: 3, max: 32 }, Rule::Username])
+            .field("email", "user1@example.com", &[Rule::Email])
+            .optional("image", Some("not-a-url"), &[Rule::Url])
+            .optional("bio", None, &[Rule::NotBlank])
+            .finish();
+
+        match result.unwrap_err().error_type {
+            AppErrorType::ValidationError(violations) => {
+                let fields = violations.iter().map(|violation| violation.field.as_str()).collect::<Vec<_>>();
+
+                assert_eq!(fields, vec!["username", "username", "image"], "Every violation should be reported");
+            },
+            _ => panic!("Validation error expected")
+        }
+    }
+
+    #[test]
+    fn test_each() {
+        let tags = vec!["rust".to_string(), " ".to_string()];
+
+        assert!(Validator::new().each("tags", Some(&tags), 10, &[Rule::NotBlank]).finish().is_err(), "Blank tag should be rejected");
+        assert!(Validator::new().each("tags", Some(&tags), 1, &[]).finish().is_err(), "Too many tags should be rejected");
+        assert!(Validator::new().each("tags", None, 1, &[Rule::NotBlank]).finish().is_ok(), "Missing tags are valid");
+    }
+}
@@ -0,0 +1,60 @@
+/// Random single-use tokens sent by email (password resets, email verification)
+/// Only the SHA-256 of a token is stored, so a leaked table can't be used to take over accounts
+
+use postgres_types::{FromSql, ToSql};
+use sha2::{Digest, Sha256};
+
+/// What a token can be used for, a token is only accepted for its own purpose
+#[derive(Clone, Copy, Debug, PartialEq, ToSql, FromSql)]
+#[postgres(name = "token_purpose")]
+pub enum TokenPurpose {
+    #[postgres(name = "password_reset")]
+    PasswordReset,
+    #[postgres(name = "email_verification")]
+    EmailVerification,
+}
+
+impl TokenPurpose {
+    pub fn description(self) -> &'static str {
+        match self {
+            TokenPurpose::PasswordReset => "password reset",
+            TokenPurpose::EmailVerification => "email verification",
+        }
+    }
+}
+
+pub struct OneTimeToken {
+    /// Sent to the user, never stored
+    pub token: String,
+    pub hash: String,
+}
+
+impl OneTimeToken {
+    pub fn generate() -> OneTimeToken {
+        let bytes: [u8; 32] = rand::random();
+        let token = base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD);
+        let hash = OneTimeToken::hash(&token);
+
+        OneTimeToken { token, hash }
+    }
+
+    pub fn hash(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::OneTimeToken;
+
+    #[test]
+    fn test_generate() {
+        let first = OneTimeToken::generate();
+        let second = OneTimeToken::generate();
+
+        assert_ne!(first.token, second.token, "Tokens should be random");
+        assert_eq!(OneTimeToken::hash(&first.token), first.hash, "Hash should be reproducible from the token");
+        assert!(!first.hash.contains(&first.token), "Hash should not contain the token");
+    }
+}
@@ -0,0 +1,211 @@
+use deadpool_postgres::{Pool, Client};
+use std::{collections::HashMap, sync::Arc};
+use slog_scope::{error, info};
+use crate::models::comment::{Comment, CreateComment};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use crate::errors::{AppError, AppErrorType};
+use tokio_postgres::error::{Error, SqlState};
+use uuid::Uuid;
+use async_trait::async_trait;
+use dataloader::{BatchFn, cached::Loader};
+
+pub struct CommentRepository {
+    pool: Arc<Pool>,
+}
+
+pub struct CommentBatcher {
+    pool: Arc<Pool>,
+}
+
+pub type CommentLoader = Loader<Uuid, Vec<Comment>, AppError, CommentBatcher>;
+
+pub fn get_comment_loader(pool: Arc<Pool>) -> CommentLoader {
+    Loader::new(CommentBatcher { pool })
+        // https://github.com/cksac/dataloader-rs/issues/12
+        .with_yield_count(100)
+}
+
+impl CommentRepository {
+
+    pub fn new(pool: Arc<Pool>) -> CommentRepository {
+        CommentRepository { pool }
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Comment, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get");
+                err
+            })?;
+
+        let statement = client.prepare("select * from comments where id = $1").await?;
+
+        client
+            .query(&statement, &[&id])
+            .await
+            .map_err(|err| {
+                error!("Error getting comments. {}", err; "query" => "get");
+                err
+            })?
+            .iter()
+            .map(|row| Comment::from_row_ref(row))
+            .collect::<Result<Vec<Comment>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+
+    pub async fn create(&self, author_id: Uuid, input: CreateComment) -> Result<Comment, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "create");
+                err
+            })?;
+
+        let statement = client
+            .prepare("insert into comments (author_id, post_id, body) values ($1, $2, $3) returning *")
+            .await?;
+
+        let comment = client.query(&statement, &[
+                &author_id,
+                &input.post_id,
+                &input.body
+            ])
+            .await
+            .map_err(|err: Error| {
+                let foreign_key_error = err.code()
+                    .map(|code| code == &SqlState::FOREIGN_KEY_VIOLATION);
+
+                match foreign_key_error {
+                    Some(true) => AppError {
+                            cause: Some(err.to_string()),
+                            message: Some(format!("Post with id {} doesn't exists.", input.post_id)),
+                            error_type: AppErrorType::InvalidField,
+                            field: Some("postId".to_string())
+                        },
+                    _ => AppError::from(err)
+                }
+            })?
+            .iter()
+            .map(|row| Comment::from_row_ref(row))
+            .collect::<Result<Vec<Comment>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                message: Some("Error creating Comment.".to_string()),
+                cause: Some("Unknown error.".to_string()),
+                error_type: AppErrorType::DbError,
+                field: None
+            })?;
+
+        Ok(comment)
+    }
+
+    pub async fn delete(&self, id: Uuid) -> Result<Comment, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "delete");
+                err
+            })?;
+
+        let statement = client.prepare("delete from comments where id = $1 returning *").await?;
+
+        client
+            .query(&statement, &[&id])
+            .await
+            .map_err(|err| {
+                error!("Error deleting comment. {}", err; "query" => "delete");
+                err
+            })?
+            .iter()
+            .map(|row| Comment::from_row_ref(row))
+            .collect::<Result<Vec<Comment>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+}
+
+impl CommentBatcher {
+    pub async fn get_comments_by_post_ids(&self, hashmap: &mut HashMap<Uuid, Vec<Comment>>, ids: Vec<Uuid>) -> Result<(), AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get_comments_by_post_ids");
+                err
+            })?;
+
+        let statement = client
+            .prepare("select * from comments where post_id = ANY($1) order by created_at")
+            .await?;
+
+        client
+            .query(&statement, &[&ids])
+            .await
+            .map_err(|err| {
+                error!("Error getting comments. {}", err; "query" => "get_comments_by_post_ids");
+                err
+            })?
+            .iter()
+            .map(|row| Comment::from_row_ref(row))
+            .collect::<Result<Vec<Comment>, _>>()
+            .map_err(|err| {
+                error!("Error getting parsing comments. {}", err; "query" => "get_comments_by_post_ids");
+                err
+            })?
+            .iter()
+            .fold(
+                hashmap,
+                |map: &mut HashMap<Uuid, Vec<Comment>>, comment: &Comment| {
+                    let vec = map
+                        .entry(comment.post_id)
+                        .or_insert_with(|| Vec::<Comment>::new());
+                    vec.push(comment.clone());
+                    map
+                }
+            );
+
+        Ok(())
+
+    }
+}
+
+#[async_trait]
+impl BatchFn<Uuid, Vec<Comment>> for CommentBatcher {
+    type Error = AppError;
+
+    async fn load(&self, keys: &[Uuid]) -> HashMap<Uuid, Result<Vec<Comment>, AppError>> {
+
+        info!("Loading batch {:?}", keys);
+
+        let mut comments_map = HashMap::new();
+
+        let result: Result<(), AppError> = self.get_comments_by_post_ids(&mut comments_map, keys.into()).await;
+
+        keys
+            .iter()
+            .map(move |id| {
+                let entry =
+                    comments_map.entry(*id)
+                        .or_insert_with(|| vec![])
+                        .clone();
+
+                    (id.clone(), result.clone().map(|_| entry))
+                })
+                .collect::<HashMap<_, _>>()
+    }
+}
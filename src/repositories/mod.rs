@@ -1,2 +1,38 @@
+pub mod comment;
+pub mod one_time_token;
 pub mod user;
-pub mod post;
\ No newline at end of file
+pub mod post;
+pub mod sitemap;
+pub mod tag;
+
+use crate::models::pagination::PageQuery;
+use tokio_postgres::types::ToSql;
+
+/// Keyset conditions on `(created_at, id)` for the cursors in `page`.
+/// Cursor values are appended to `params` after any filter parameters.
+pub fn keyset_conditions<'a>(page: &'a PageQuery, params: &mut Vec<&'a (dyn ToSql + Sync)>) -> String {
+    let mut conditions = String::new();
+
+    if let Some(after) = &page.after {
+        params.push(&after.created_at);
+        params.push(&after.id);
+        conditions.push_str(&format!(" and (created_at, id) < (${}, ${})", params.len() - 1, params.len()));
+    }
+
+    if let Some(before) = &page.before {
+        params.push(&before.created_at);
+        params.push(&before.id);
+        conditions.push_str(&format!(" and (created_at, id) > (${}, ${})", params.len() - 1, params.len()));
+    }
+
+    conditions
+}
+
+/// Newest first, reversed when paginating backwards
+pub fn keyset_order(page: &PageQuery) -> &'static str {
+    if page.backwards {
+        "created_at asc, id asc"
+    } else {
+        "created_at desc, id desc"
+    }
+}
@@ -0,0 +1,157 @@
+use deadpool_postgres::{Pool, Client};
+use std::sync::Arc;
+use slog_scope::error;
+use chrono::{DateTime, Utc};
+use crate::models::user::User;
+use crate::one_time_token::TokenPurpose;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::types::ToSql;
+use crate::errors::{AppError, AppErrorType};
+use uuid::Uuid;
+
+pub struct OneTimeTokenRepository {
+    pool: Arc<Pool>,
+}
+
+impl OneTimeTokenRepository {
+
+    pub fn new(pool: Arc<Pool>) -> OneTimeTokenRepository {
+        OneTimeTokenRepository { pool }
+    }
+
+    /// Stores a new token, invalidating the ones previously issued to the user for the same purpose
+    pub async fn create(&self, purpose: TokenPurpose, user_id: Uuid, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "create");
+                err
+            })?;
+
+        let statement = client
+            .prepare(
+                "with invalidated as (\
+                    update one_time_tokens set used_at = current_timestamp \
+                    where user_id = $1 and purpose = $2 and used_at is null\
+                ) \
+                insert into one_time_tokens (user_id, purpose, token_hash, expires_at) values ($1, $2, $3, $4)"
+            )
+            .await?;
+
+        client
+            .execute(&statement, &[&user_id, &purpose, &token_hash, &expires_at])
+            .await
+            .map_err(|err| {
+                error!("Error creating {} token. {}", purpose.description(), err; "query" => "create");
+                err
+            })?;
+
+        Ok(())
+    }
+
+    /// Whether the token exists for `purpose`, unused and unexpired
+    /// Lets callers reject bad tokens before doing expensive work, `consume` still checks it again
+    pub async fn is_valid(&self, purpose: TokenPurpose, token_hash: &str) -> Result<bool, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "is_valid");
+                err
+            })?;
+
+        let statement = client
+            .prepare(
+                "select id from one_time_tokens \
+                where token_hash = $1 and purpose = $2 and used_at is null and expires_at > current_timestamp"
+            )
+            .await?;
+
+        let rows = client
+            .query(&statement, &[&token_hash, &purpose])
+            .await
+            .map_err(|err| {
+                error!("Error getting {} token. {}", purpose.description(), err; "query" => "is_valid");
+                err
+            })?;
+
+        Ok(!rows.is_empty())
+    }
+
+    /// Consumes the token and sets the user's new password, in a single statement
+    /// Access tokens issued before the change are revoked through `password_changed_at`
+    pub async fn reset_password(&self, token_hash: &str, password_hash: &str) -> Result<User, AppError> {
+        self.consume(
+            TokenPurpose::PasswordReset,
+            token_hash,
+            "password = $3, password_changed_at = current_timestamp",
+            &[&password_hash]
+        ).await
+    }
+
+    /// Consumes the token and marks the user's email address as verified
+    pub async fn verify_email(&self, token_hash: &str) -> Result<User, AppError> {
+        self.consume(
+            TokenPurpose::EmailVerification,
+            token_hash,
+            "email_verified_at = coalesce(email_verified_at, current_timestamp)",
+            &[]
+        ).await
+    }
+
+    /// Marks a valid token as used and applies `assignments` to its user, in a single statement
+    /// Extra `params` start at `$3`
+    async fn consume(
+        &self,
+        purpose: TokenPurpose,
+        token_hash: &str,
+        assignments: &str,
+        params: &[&(dyn ToSql + Sync)]
+    ) -> Result<User, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "consume");
+                err
+            })?;
+
+        let statement = client
+            .prepare(&format!(
+                "with consumed as (\
+                    update one_time_tokens set used_at = current_timestamp \
+                    where token_hash = $1 and purpose = $2 and used_at is null and expires_at > current_timestamp \
+                    returning user_id\
+                ) \
+                update users set {} from consumed where users.id = consumed.user_id returning users.*",
+                assignments
+            ))
+            .await?;
+
+        let mut all_params: Vec<&(dyn ToSql + Sync)> = vec![&token_hash, &purpose];
+        all_params.extend_from_slice(params);
+
+        client
+            .query(&statement, &all_params)
+            .await
+            .map_err(|err| {
+                error!("Error consuming {} token. {}", purpose.description(), err; "query" => "consume");
+                err
+            })?
+            .iter()
+            .map(|row| User::from_row_ref(row))
+            .collect::<Result<Vec<User>, _>>()?
+            .pop()
+            .ok_or_else(|| invalid_token_error(purpose))
+    }
+}
+
+pub fn invalid_token_error(purpose: TokenPurpose) -> AppError {
+    AppError {
+        message: Some(format!("Invalid or expired {} token.", purpose.description())),
+        cause: None,
+        error_type: AppErrorType::InvalidField,
+        field: Some("token".to_string())
+    }
+}
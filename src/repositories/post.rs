@@ -1,14 +1,26 @@
-use deadpool_postgres::{Pool, Client};
+use deadpool_postgres::{Pool, Client, Transaction};
 use std::{collections::HashMap, sync::Arc};
 use slog_scope::{error, info};
-use crate::models::post::{Post, CreatePost};
+use crate::models::{
+    pagination::{decode_offset, Connection, PageRequest, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+    post::{slugify, Post, PostStatus, CreatePost, UpdatePost, SearchHit},
+    tag::normalize_tag
+};
+use crate::repositories::{keyset_conditions, keyset_order};
+use crate::metrics::POST_BATCH_SIZE;
 use tokio_pg_mapper::FromTokioPostgresRow;
 use crate::errors::{AppError, AppErrorType};
-use tokio_postgres::error::{Error, SqlState};
+use tokio_postgres::{error::{Error, SqlState}, types::ToSql};
 use uuid::Uuid;
+use chrono::{DateTime, Utc};
 use async_trait::async_trait;
 use dataloader::{BatchFn, cached::Loader};
 
+const MAX_SLUG_ATTEMPTS: u32 = 5;
+
+/// Condition for posts visible to everyone
+pub const PUBLISHED: &str = "status <> 'draft' and published_at <= current_timestamp";
+
 pub struct PostRepository {
     pool: Arc<Pool>,
 }
@@ -17,7 +29,16 @@ pub struct PostBatcher {
     pool: Arc<Pool>,
 }
 
-pub type PostLoader = Loader<Uuid, Vec<Post>, AppError, PostBatcher>;
+/// Key for a page of a user's posts
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UserPostsKey {
+    pub author_id: Uuid,
+    pub page: PageRequest,
+    /// Drafts and scheduled posts are only listed for their author
+    pub include_unpublished: bool,
+}
+
+pub type PostLoader = Loader<UserPostsKey, Connection<Post>, AppError, PostBatcher>;
 
 pub fn get_post_loader(pool: Arc<Pool>) -> PostLoader {
     Loader::new(PostBatcher { pool })
@@ -56,11 +77,46 @@ impl PostRepository {
             .ok_or(AppError {
                 cause: None,
                 message: None,
-                error_type: AppErrorType::NotFoundError
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+
+    pub async fn get_by_slug(&self, slug: &str) -> Result<Post, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get_by_slug");
+                err
+            })?;
+
+        let statement = client.prepare("select * from posts where slug = $1").await?;
+
+        client
+            .query(&statement, &[&slug])
+            .await
+            .map_err(|err| {
+                error!("Error getting posts. {}", err; "query" => "get_by_slug");
+                err
+            })?
+            .iter()
+            .map(|row| Post::from_row_ref(row))
+            .collect::<Result<Vec<Post>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
             })
     }
 
-    pub async fn all(&self) -> Result<Vec<Post>, AppError> {
+    /// Published posts, optionally only those with the given tag
+    pub async fn all(&self, tag: Option<String>, page: &PageRequest) -> Result<Connection<Post>, AppError> {
+        let page = page.parse()?;
+        let tag = tag.map(|tag| normalize_tag(&tag));
+
         let client: Client = self.pool
             .get()
             .await
@@ -69,24 +125,156 @@ impl PostRepository {
                 err
             })?;
 
-        let statement = client.prepare("select * from posts").await?;
+        let mut filter = PUBLISHED.to_string();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![];
 
-        let users = client
-            .query(&statement, &[])
+        if let Some(tag) = &tag {
+            params.push(tag);
+            filter.push_str(" and id in (\
+                select posts_tags.post_id from posts_tags \
+                join tags on tags.id = posts_tags.tag_id where tags.name = $1\
+            )");
+        }
+
+        let filter_params = params.len();
+        let conditions = keyset_conditions(&page, &mut params);
+
+        let statement = client
+            .prepare(&format!(
+                "select * from posts where {}{} order by {} limit {}",
+                filter, conditions, keyset_order(&page), page.limit + 1
+            ))
+            .await?;
+
+        let posts = client
+            .query(&statement, &params[..])
             .await
             .map_err(|err| {
-                error!("Error getting users. {}", err; "query" => "all");
+                error!("Error getting posts. {}", err; "query" => "all");
                 err
             })?
             .iter()
             .map(|row| Post::from_row_ref(row))
             .collect::<Result<Vec<Post>, _>>()
             .map_err(|err| {
-                error!("Error getting parsing users. {}", err; "query" => "all");
+                error!("Error getting parsing posts. {}", err; "query" => "all");
                 err
             })?;
 
-        Ok(users)
+        let count_statement = client.prepare(&format!("select count(*) from posts where {}", filter)).await?;
+        let total_count: i64 = client.query_one(&count_statement, &params[..filter_params]).await?.get(0);
+
+        Ok(Connection::from_rows(posts, &page, total_count))
+    }
+
+    /// Published posts matching `query`, best matches first
+    pub async fn search(&self, query: &str, first: Option<i32>, after: Option<String>) -> Result<Connection<SearchHit>, AppError> {
+        let limit = first.map(|first| first as i64).unwrap_or(DEFAULT_PAGE_SIZE);
+
+        if limit < 0 || limit > MAX_PAGE_SIZE {
+            return Err(AppError {
+                message: Some(format!("`first` must be between 0 and {}", MAX_PAGE_SIZE)),
+                cause: None,
+                error_type: AppErrorType::InvalidField,
+                field: Some("first".to_string())
+            });
+        }
+
+        let offset = after.as_deref()
+            .map(decode_offset)
+            .transpose()
+            .map_err(|err| err.with_field("after"))?
+            .unwrap_or(0);
+
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "search");
+                err
+            })?;
+
+        // The body is escaped before highlighting so the snippet is safe to render as HTML
+        let statement = client
+            .prepare(&format!(
+                "select posts.*, ts_rank(search, query) as rank, \
+                ts_headline('english', replace(replace(replace(body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), query, \
+                    'StartSel=<mark>, StopSel=</mark>, MaxWords=35, MinWords=15, MaxFragments=2') as snippet \
+                from posts, websearch_to_tsquery('english', $1) as query \
+                where search @@ query and {} \
+                order by rank desc, created_at desc, id desc limit $2 offset $3",
+                PUBLISHED
+            ))
+            .await?;
+
+        let hits = client
+            .query(&statement, &[&query, &(limit + 1), &offset])
+            .await
+            .map_err(|err| {
+                error!("Error searching posts. {}", err; "query" => "search");
+                err
+            })?
+            .iter()
+            .map(|row| Post::from_row_ref(row).map(|post| SearchHit {
+                post,
+                rank: row.get("rank"),
+                snippet: row.get("snippet")
+            }))
+            .collect::<Result<Vec<SearchHit>, _>>()
+            .map_err(|err| {
+                error!("Error getting parsing posts. {}", err; "query" => "search");
+                err
+            })?;
+
+        let count_statement = client
+            .prepare(&format!(
+                "select count(*) from posts, websearch_to_tsquery('english', $1) as query where search @@ query and {}",
+                PUBLISHED
+            ))
+            .await?;
+
+        let total_count: i64 = client.query_one(&count_statement, &[&query]).await?.get(0);
+
+        Ok(Connection::from_offset(hits, offset, limit, total_count))
+    }
+
+    /// Most recently published posts along with their author's username, for feeds
+    pub async fn latest(&self, author_id: Option<Uuid>, limit: i64) -> Result<Vec<(Post, String)>, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "latest");
+                err
+            })?;
+
+        let statement = client
+            .prepare(&format!(
+                "select posts.*, users.username as author_username \
+                from (select * from posts where {}) as posts \
+                join users on users.id = posts.author_id \
+                where ($1::uuid is null or posts.author_id = $1) \
+                order by posts.published_at desc, posts.id desc limit $2",
+                PUBLISHED
+            ))
+            .await?;
+
+        let posts = client
+            .query(&statement, &[&author_id, &limit])
+            .await
+            .map_err(|err| {
+                error!("Error getting posts. {}", err; "query" => "latest");
+                err
+            })?
+            .iter()
+            .map(|row| Post::from_row_ref(row).map(|post| (post, row.get("author_username"))))
+            .collect::<Result<Vec<(Post, String)>, _>>()
+            .map_err(|err| {
+                error!("Error getting parsing posts. {}", err; "query" => "latest");
+                err
+            })?;
+
+        Ok(posts)
     }
 
     #[allow(dead_code)]
@@ -119,47 +307,122 @@ impl PostRepository {
         Ok(users)
     }
 
-    pub async fn create(&self, input: CreatePost) -> Result<Post, AppError> {
-        let client: Client = self.pool
-            .get()
+    /// Inserts the post within `transaction`, so related rows can be written atomically
+    pub async fn create(&self, transaction: &Transaction<'_>, author_id: Uuid, input: CreatePost) -> Result<Post, AppError> {
+        // A failed statement would abort the transaction, so slug collisions are skipped instead
+        let statement = transaction
+            .prepare(
+                "insert into posts (author_id, slug, title, description, body) values ($1, $2, $3, $4, $5) \
+                on conflict (slug) do nothing returning *"
+            )
+            .await?;
+
+        // Generated slugs get a numeric suffix on collision instead of failing
+        let (mut slug, generated) = match input.slug {
+            Some(s) => (s, false),
+            None => (slugify(&input.title), true)
+        };
+        let base_slug = slug.clone();
+        let mut attempts = 0;
+
+        let rows = loop {
+            let rows = transaction.query(&statement, &[
+                    &author_id,
+                    &slug,
+                    &input.title,
+                    &input.description,
+                    &input.body
+                ])
+                .await
+                .map_err(|err: Error| match err.code() {
+                    Some(c) if c == &SqlState::FOREIGN_KEY_VIOLATION => AppError {
+                        cause: Some(err.to_string()),
+                        message: Some(format!("Author with id {} doesn't exists.", author_id)),
+                        error_type: AppErrorType::InvalidField,
+                        field: None
+                    },
+                    _ => {
+                        error!("Error creating post. {}", err; "query" => "create");
+                        AppError::from(err)
+                    }
+                })?;
+
+            if !rows.is_empty() {
+                break rows;
+            }
+
+            if !generated || attempts >= MAX_SLUG_ATTEMPTS {
+                return Err(slug_exists_error(None, &slug));
+            }
+
+            attempts += 1;
+            slug = self.next_slug(transaction, &base_slug).await?;
+        };
+
+        let post = rows
+            .iter()
+            .map(|row| Post::from_row_ref(row))
+            .collect::<Result<Vec<Post>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                message: Some("Error creating Post.".to_string()),
+                cause: Some("Unknown error.".to_string()),
+                error_type: AppErrorType::DbError,
+                field: None
+            })?;
+
+        Ok(post)
+    }
+
+    /// Finds the first free `<base>-<n>` slug
+    async fn next_slug(&self, transaction: &Transaction<'_>, base: &str) -> Result<String, AppError> {
+        // Only numeric suffixes count, so "hello-world-2024" isn't taken for the 2024th "hello-world"
+        // Generated slugs only contain `a-z`, `0-9` and `-`, none of them special in the pattern
+        let statement = transaction
+            .prepare("select slug from posts where slug ~ ('^' || $1 || '-[0-9]{1,6}$')")
+            .await?;
+
+        let suffix = transaction
+            .query(&statement, &[&base])
             .await
             .map_err(|err| {
-                error!("Error getting client {}", err; "query" => "create");
+                error!("Error getting slugs. {}", err; "query" => "next_slug");
                 err
-            })?;
+            })?
+            .iter()
+            .filter_map(|row| {
+                let slug: &str = row.get(0);
+                slug[base.len() + 1..].parse::<u32>().ok()
+            })
+            .max()
+            .unwrap_or(1);
 
-        let statement = client
-            .prepare("insert into posts (author_id, slug, title, description, body) values ($1, $2, $3, $4, $5) returning *")
-            .await?;
+        Ok(format!("{}-{}", base, suffix + 1))
+    }
 
-        let slug = match input.slug {
-            Some(s) => s,
-            None => Uuid::new_v4().to_string()
-        };
+    /// Updates the provided fields, leaving the rest untouched
+    /// Fails with a conflict when `expected_updated_at` is given and the post changed since
+    pub async fn update(&self, transaction: &Transaction<'_>, id: Uuid, input: UpdatePost) -> Result<Post, AppError> {
+        let statement = transaction
+            .prepare(
+                "update posts set slug = coalesce($2, slug), title = coalesce($3, title), description = coalesce($4, description), body = coalesce($5, body) \
+                where id = $1 and ($6::timestamptz is null or updated_at = $6) returning *"
+            )
+            .await?;
 
-        let post = client.query(&statement, &[
-                &input.author_id,
-                &slug,
+        transaction.query(&statement, &[
+                &id,
+                &input.slug,
                 &input.title,
                 &input.description,
-                &input.body
+                &input.body,
+                &input.expected_updated_at
             ])
             .await
             .map_err(|err: Error| {
                 match err.code() {
-                    Some(code) => match code {
-                        c if c == &SqlState::UNIQUE_VIOLATION => AppError {
-                            cause: Some(err.to_string()),
-                            message: Some(format!("Slug {} already exists.", slug)),
-                            error_type: AppErrorType::InvalidField
-                        },
-                        c if c == &SqlState::FOREIGN_KEY_VIOLATION=> AppError {
-                            cause: Some(err.to_string()),
-                            message: Some(format!("Author with id {} doesn't exists.", slug)),
-                            error_type: AppErrorType::InvalidField
-                        },
-                        _ => AppError::from(err)
-                    }
+                    Some(c) if c == &SqlState::UNIQUE_VIOLATION =>
+                        slug_exists_error(Some(err), input.slug.as_deref().unwrap_or_default()),
                     _ => AppError::from(err)
                 }
             })?
@@ -167,18 +430,103 @@ impl PostRepository {
             .map(|row| Post::from_row_ref(row))
             .collect::<Result<Vec<Post>, _>>()?
             .pop()
+            .ok_or(match input.expected_updated_at {
+                Some(_) => AppError {
+                    cause: None,
+                    message: Some("The post was modified by someone else, reload it and try again.".to_string()),
+                    error_type: AppErrorType::Conflict,
+                    field: Some("expectedUpdatedAt".to_string())
+                },
+                None => AppError {
+                    cause: None,
+                    message: None,
+                    error_type: AppErrorType::NotFoundError,
+                    field: None
+                }
+            })
+    }
+
+    pub async fn set_status(&self, id: Uuid, status: PostStatus, published_at: Option<DateTime<Utc>>) -> Result<Post, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "set_status");
+                err
+            })?;
+
+        let statement = client
+            .prepare("update posts set status = $2, published_at = $3 where id = $1 returning *")
+            .await?;
+
+        client
+            .query(&statement, &[&id, &status, &published_at])
+            .await
+            .map_err(|err| {
+                error!("Error updating post status. {}", err; "query" => "set_status");
+                err
+            })?
+            .iter()
+            .map(|row| Post::from_row_ref(row))
+            .collect::<Result<Vec<Post>, _>>()?
+            .pop()
             .ok_or(AppError {
-                message: Some("Error creating Post.".to_string()),
-                cause: Some("Unknown error.".to_string()),
-                error_type: AppErrorType::DbError,
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+
+    /// Deletes the post along with its comments
+    pub async fn delete(&self, id: Uuid) -> Result<Post, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "delete");
+                err
             })?;
 
-        Ok(post)
+        let statement = client
+            .prepare("with deleted_comments as (delete from comments where post_id = $1) delete from posts where id = $1 returning *")
+            .await?;
+
+        client
+            .query(&statement, &[&id])
+            .await
+            .map_err(|err| {
+                error!("Error deleting post. {}", err; "query" => "delete");
+                err
+            })?
+            .iter()
+            .map(|row| Post::from_row_ref(row))
+            .collect::<Result<Vec<Post>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+}
+
+fn slug_exists_error(err: Option<Error>, slug: &str) -> AppError {
+    AppError {
+        cause: err.map(|err| err.to_string()),
+        message: Some(format!("Slug {} already exists.", slug)),
+        error_type: AppErrorType::InvalidField,
+        field: Some("slug".to_string())
     }
 }
 
 impl PostBatcher {
-    pub async fn get_posts_by_user_ids(&self, hashmap: &mut HashMap<Uuid, Vec<Post>>, ids: Vec<Uuid>) -> Result<(), AppError> {
+    /// Loads one page of posts for each user, with the same connection arguments for all of them
+    pub async fn get_posts_by_user_ids(&self, ids: &[Uuid], page: &PageRequest, include_unpublished: bool) -> Result<HashMap<Uuid, Connection<Post>>, AppError> {
+        let page = page.parse()?;
+        let visibility = if include_unpublished { "true" } else { PUBLISHED };
+
         let client: Client = self.pool
             .get()
             .await
@@ -187,10 +535,21 @@ impl PostBatcher {
                 err
             })?;
 
-        let statement = client.prepare("select * from posts where author_id = ANY($1)").await?; 
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&ids];
+        let conditions = keyset_conditions(&page, &mut params);
 
-        client
-            .query(&statement, &[&ids])
+        let statement = client
+            .prepare(&format!(
+                "select * from (\
+                    select *, row_number() over (partition by author_id order by {}) as page_row \
+                    from posts where author_id = ANY($1) and {}{}\
+                ) as paged where page_row <= {} order by page_row",
+                keyset_order(&page), visibility, conditions, page.limit + 1
+            ))
+            .await?;
+
+        let mut posts_map = client
+            .query(&statement, &params[..])
             .await
             .map_err(|err| {
                 error!("Error getting posts. {}", err; "query" => "get_posts_by_user_ids");
@@ -203,45 +562,85 @@ impl PostBatcher {
                 error!("Error getting parsing posts. {}", err; "query" => "get_posts_by_user_ids");
                 err
             })?
-            .iter()
+            .into_iter()
             .fold(
-                hashmap,
-                |map: &mut HashMap<Uuid, Vec<Post>>, post: &Post| {
-                    let vec = map
+                HashMap::new(),
+                |mut map: HashMap<Uuid, Vec<Post>>, post: Post| {
+                    map
                         .entry(post.author_id)
-                        .or_insert_with(|| Vec::<Post>::new());
-                    vec.push(post.clone());
+                        .or_insert_with(|| Vec::<Post>::new())
+                        .push(post);
                     map
                 }
             );
 
-        Ok(())
+        let count_statement = client
+            .prepare(&format!(
+                "select author_id, count(*) from posts where author_id = ANY($1) and {} group by author_id",
+                visibility
+            ))
+            .await?;
+
+        let counts = client
+            .query(&count_statement, &[&ids])
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect::<HashMap<Uuid, i64>>();
+
+        Ok(ids
+            .iter()
+            .map(|id| {
+                let posts = posts_map.remove(id).unwrap_or_default();
+                let total_count = counts.get(id).cloned().unwrap_or_default();
 
+                (*id, Connection::from_rows(posts, &page, total_count))
+            })
+            .collect())
     }
 }
 
 #[async_trait]
-impl BatchFn<Uuid, Vec<Post>> for PostBatcher {
+impl BatchFn<UserPostsKey, Connection<Post>> for PostBatcher {
     type Error = AppError;
 
-    async fn load(&self, keys: &[Uuid]) -> HashMap<Uuid, Result<Vec<Post>, AppError>> {
+    async fn load(&self, keys: &[UserPostsKey]) -> HashMap<UserPostsKey, Result<Connection<Post>, AppError>> {
 
         info!("Loading batch {:?}", keys);
 
-        let mut posts_map = HashMap::new();
+        POST_BATCH_SIZE.observe(keys.len() as f64);
 
-        let result: Result<(), AppError> = self.get_posts_by_user_ids(&mut posts_map, keys.into()).await;
+        // Users sharing the same arguments are loaded with a single query
+        let mut groups: HashMap<(PageRequest, bool), Vec<Uuid>> = HashMap::new();
 
-        keys
-            .iter()
-            .map(move |id| {
-                let entry = 
-                    posts_map.entry(*id)
-                        .or_insert_with(|| vec![])
-                        .clone();
-
-                    (id.clone(), result.clone().map(|_| entry))
-                })
-                .collect::<HashMap<_, _>>()
+        for key in keys {
+            groups
+                .entry((key.page.clone(), key.include_unpublished))
+                .or_insert_with(|| vec![])
+                .push(key.author_id);
+        }
+
+        let mut connections = HashMap::new();
+
+        for ((page, include_unpublished), ids) in groups {
+            let result = self.get_posts_by_user_ids(&ids, &page, include_unpublished).await;
+
+            for id in ids {
+                let entry = result
+                    .as_ref()
+                    .map(|map| map[&id].clone())
+                    .map_err(|err| err.clone());
+
+                let key = UserPostsKey {
+                    author_id: id,
+                    page: page.clone(),
+                    include_unpublished
+                };
+
+                connections.insert(key, entry);
+            }
+        }
+
+        connections
     }
-}
\ No newline at end of file
+}
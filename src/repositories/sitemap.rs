@@ -0,0 +1,96 @@
+use deadpool_postgres::{Pool, Client};
+use std::sync::Arc;
+use slog_scope::error;
+use chrono::{DateTime, Utc};
+use crate::errors::AppError;
+use crate::repositories::post::PUBLISHED;
+
+/// A public page listed in the sitemap
+#[derive(Clone, Debug, PartialEq)]
+pub enum SitemapEntry {
+    Post { slug: String, updated_at: DateTime<Utc> },
+    User { username: String, updated_at: DateTime<Utc> },
+}
+
+pub struct SitemapRepository {
+    pool: Arc<Pool>,
+}
+
+impl SitemapRepository {
+
+    pub fn new(pool: Arc<Pool>) -> SitemapRepository {
+        SitemapRepository { pool }
+    }
+
+    pub async fn count(&self) -> Result<i64, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "count");
+                err
+            })?;
+
+        let statement = client
+            .prepare(&format!(
+                "select (select count(*) from posts where {}) + (select count(*) from users)",
+                PUBLISHED
+            ))
+            .await?;
+
+        let count: i64 = client
+            .query_one(&statement, &[])
+            .await
+            .map_err(|err| {
+                error!("Error counting sitemap entries. {}", err; "query" => "count");
+                err
+            })?
+            .get(0);
+
+        Ok(count)
+    }
+
+    /// Published posts followed by user profiles, in a stable order
+    pub async fn entries(&self, limit: i64, offset: i64) -> Result<Vec<SitemapEntry>, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "entries");
+                err
+            })?;
+
+        let statement = client
+            .prepare(&format!(
+                "select kind, key, updated_at from (\
+                    select 0 as kind, slug as key, updated_at, created_at, id from posts where {} \
+                    union all \
+                    select 1 as kind, username as key, updated_at, created_at, id from users\
+                ) as entries order by kind, created_at, id limit $1 offset $2",
+                PUBLISHED
+            ))
+            .await?;
+
+        let entries = client
+            .query(&statement, &[&limit, &offset])
+            .await
+            .map_err(|err| {
+                error!("Error getting sitemap entries. {}", err; "query" => "entries");
+                err
+            })?
+            .iter()
+            .map(|row| {
+                let kind: i32 = row.get("kind");
+                let key: String = row.get("key");
+                let updated_at: DateTime<Utc> = row.get("updated_at");
+
+                match kind {
+                    0 => SitemapEntry::Post { slug: key, updated_at },
+                    _ => SitemapEntry::User { username: key, updated_at }
+                }
+            })
+            .collect();
+
+        Ok(entries)
+    }
+}
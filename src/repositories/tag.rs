@@ -0,0 +1,182 @@
+use deadpool_postgres::{Pool, Client, Transaction};
+use std::{collections::HashMap, sync::Arc};
+use slog_scope::{error, info};
+use crate::models::tag::{normalize_tags, Tag, TagCount};
+use crate::repositories::post::PUBLISHED;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use crate::errors::AppError;
+use uuid::Uuid;
+use async_trait::async_trait;
+use dataloader::{BatchFn, cached::Loader};
+
+pub struct TagRepository {
+    pool: Arc<Pool>,
+}
+
+pub struct TagBatcher {
+    pool: Arc<Pool>,
+}
+
+pub type TagLoader = Loader<Uuid, Vec<Tag>, AppError, TagBatcher>;
+
+pub fn get_tag_loader(pool: Arc<Pool>) -> TagLoader {
+    Loader::new(TagBatcher { pool })
+        // https://github.com/cksac/dataloader-rs/issues/12
+        .with_yield_count(100)
+}
+
+impl TagRepository {
+
+    pub fn new(pool: Arc<Pool>) -> TagRepository {
+        TagRepository { pool }
+    }
+
+    /// Tags with the number of published posts using them, most used first
+    pub async fn all(&self) -> Result<Vec<TagCount>, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "all");
+                err
+            })?;
+
+        let statement = client
+            .prepare(&format!(
+                "select tags.*, count(published.id) as post_count from tags \
+                left join posts_tags on posts_tags.tag_id = tags.id \
+                left join (select id from posts where {}) as published on published.id = posts_tags.post_id \
+                group by tags.id order by post_count desc, tags.name",
+                PUBLISHED
+            ))
+            .await?;
+
+        let tags = client
+            .query(&statement, &[])
+            .await
+            .map_err(|err| {
+                error!("Error getting tags. {}", err; "query" => "all");
+                err
+            })?
+            .iter()
+            .map(|row| Tag::from_row_ref(row).map(|tag| TagCount {
+                tag,
+                post_count: row.get("post_count")
+            }))
+            .collect::<Result<Vec<TagCount>, _>>()
+            .map_err(|err| {
+                error!("Error getting parsing tags. {}", err; "query" => "all");
+                err
+            })?;
+
+        Ok(tags)
+    }
+
+    /// Replaces the tags of a post, creating any tag that doesn't exist yet
+    /// Runs within the transaction that wrote the post
+    pub async fn set_post_tags(&self, transaction: &Transaction<'_>, post_id: Uuid, tags: &[String]) -> Result<(), AppError> {
+        let statement = transaction
+            .prepare(
+                // The no-op update returns the ids of existing tags too, including ones
+                // inserted by a concurrent transaction that this snapshot can't see
+                "with tag_ids as (\
+                    insert into tags (name) select unnest($2::varchar[]) \
+                    on conflict (name) do update set name = excluded.name returning id\
+                ), removed as (\
+                    delete from posts_tags where post_id = $1 \
+                    and tag_id not in (select id from tag_ids)\
+                ) \
+                insert into posts_tags (post_id, tag_id) \
+                select $1, id from tag_ids \
+                on conflict do nothing"
+            )
+            .await?;
+
+        let tags = normalize_tags(tags);
+
+        transaction
+            .execute(&statement, &[&post_id, &tags])
+            .await
+            .map_err(|err| {
+                error!("Error setting tags. {}", err; "query" => "set_post_tags");
+                err
+            })?;
+
+        Ok(())
+    }
+}
+
+impl TagBatcher {
+    pub async fn get_tags_by_post_ids(&self, hashmap: &mut HashMap<Uuid, Vec<Tag>>, ids: Vec<Uuid>) -> Result<(), AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get_tags_by_post_ids");
+                err
+            })?;
+
+        let statement = client
+            .prepare(
+                "select posts_tags.post_id, tags.* from tags \
+                join posts_tags on posts_tags.tag_id = tags.id \
+                where posts_tags.post_id = ANY($1) order by tags.name"
+            )
+            .await?;
+
+        client
+            .query(&statement, &[&ids])
+            .await
+            .map_err(|err| {
+                error!("Error getting tags. {}", err; "query" => "get_tags_by_post_ids");
+                err
+            })?
+            .iter()
+            .map(|row| Tag::from_row_ref(row).map(|tag| (row.get("post_id"), tag)))
+            .collect::<Result<Vec<(Uuid, Tag)>, _>>()
+            .map_err(|err| {
+                error!("Error getting parsing tags. {}", err; "query" => "get_tags_by_post_ids");
+                err
+            })?
+            .into_iter()
+            .fold(
+                hashmap,
+                |map: &mut HashMap<Uuid, Vec<Tag>>, (post_id, tag): (Uuid, Tag)| {
+                    let vec = map
+                        .entry(post_id)
+                        .or_insert_with(|| Vec::<Tag>::new());
+                    vec.push(tag);
+                    map
+                }
+            );
+
+        Ok(())
+
+    }
+}
+
+#[async_trait]
+impl BatchFn<Uuid, Vec<Tag>> for TagBatcher {
+    type Error = AppError;
+
+    async fn load(&self, keys: &[Uuid]) -> HashMap<Uuid, Result<Vec<Tag>, AppError>> {
+
+        info!("Loading batch {:?}", keys);
+
+        let mut tags_map = HashMap::new();
+
+        let result: Result<(), AppError> = self.get_tags_by_post_ids(&mut tags_map, keys.into()).await;
+
+        keys
+            .iter()
+            .map(move |id| {
+                let entry =
+                    tags_map.entry(*id)
+                        .or_insert_with(|| vec![])
+                        .clone();
+
+                    (id.clone(), result.clone().map(|_| entry))
+                })
+                .collect::<HashMap<_, _>>()
+    }
+}
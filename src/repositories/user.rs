@@ -1,16 +1,31 @@
 use deadpool_postgres::{Pool, Client};
-use std::sync::Arc;
-use slog_scope::error;
-use crate::models::user::{User, CreateUser};
+use std::{collections::HashMap, sync::Arc};
+use slog_scope::{error, info};
+use crate::models::{pagination::{Connection, PageRequest}, user::{Role, User, CreateUser}};
+use crate::repositories::{keyset_conditions, keyset_order};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use crate::{config::HashingService, errors::{AppError, AppErrorType}};
-use tokio_postgres::error::{Error, SqlState};
+use tokio_postgres::{error::{Error, SqlState}, types::ToSql};
 use uuid::Uuid;
+use async_trait::async_trait;
+use dataloader::{BatchFn, cached::Loader};
 
 pub struct UserRepository {
     pool: Arc<Pool>
 }
 
+pub struct UserBatcher {
+    pool: Arc<Pool>,
+}
+
+pub type UserLoader = Loader<Uuid, User, AppError, UserBatcher>;
+
+pub fn get_user_loader(pool: Arc<Pool>) -> UserLoader {
+    Loader::new(UserBatcher { pool })
+        // https://github.com/cksac/dataloader-rs/issues/12
+        .with_yield_count(100)
+}
+
 impl UserRepository {
 
     pub fn new(pool: Arc<Pool>) -> UserRepository {
@@ -42,11 +57,133 @@ impl UserRepository {
             .ok_or(AppError {
                 cause: None,
                 message: None,
-                error_type: AppErrorType::NotFoundError
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+
+    pub async fn get_by_username(&self, username: &str) -> Result<User, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get_by_username");
+                err
+            })?;
+
+        let statement = client.prepare("select * from users where username = $1").await?;
+
+        client
+            .query(&statement, &[&username])
+            .await
+            .map_err(|err| {
+                error!("Error getting users. {}", err; "query" => "get_by_username");
+                err
+            })?
+            .iter()
+            .map(|row| User::from_row_ref(row))
+            .collect::<Result<Vec<User>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
             })
     }
 
-    pub async fn all(&self) -> Result<Vec<User>, AppError> {
+    pub async fn get_by_email(&self, email: &str) -> Result<User, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get_by_email");
+                err
+            })?;
+
+        let statement = client.prepare("select * from users where email = $1").await?;
+
+        client
+            .query(&statement, &[&email])
+            .await
+            .map_err(|err| {
+                error!("Error getting users. {}", err; "query" => "get_by_email");
+                err
+            })?
+            .iter()
+            .map(|row| User::from_row_ref(row))
+            .collect::<Result<Vec<User>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+
+    /// Fails with a conflict instead of demoting the last admin
+    /// Admin rows are locked in id order first, so concurrent role changes wait for each other
+    /// and see the admins left by the previous one
+    pub async fn set_role(&self, id: Uuid, role: Role) -> Result<User, AppError> {
+        let mut client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "set_role");
+                err
+            })?;
+
+        let transaction = client.transaction().await?;
+
+        let admins = transaction
+            .query("select id from users where role = 'admin' order by id for update", &[])
+            .await
+            .map_err(|err| {
+                error!("Error locking admins. {}", err; "query" => "set_role");
+                err
+            })?
+            .iter()
+            .map(|row| row.get::<_, Uuid>("id"))
+            .collect::<Vec<Uuid>>();
+
+        if role != Role::Admin && admins == [id] {
+            return Err(AppError {
+                message: Some("At least one admin must remain.".to_string()),
+                cause: None,
+                error_type: AppErrorType::Conflict,
+                field: Some("role".to_string())
+            });
+        }
+
+        let statement = transaction.prepare("update users set role = $2 where id = $1 returning *").await?;
+
+        let user = transaction
+            .query(&statement, &[&id, &role])
+            .await
+            .map_err(|err| {
+                error!("Error updating user role. {}", err; "query" => "set_role");
+                err
+            })?
+            .iter()
+            .map(|row| User::from_row_ref(row))
+            .collect::<Result<Vec<User>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })?;
+
+        transaction.commit().await?;
+
+        Ok(user)
+    }
+
+    pub async fn all(&self, page: &PageRequest) -> Result<Connection<User>, AppError> {
+        let page = page.parse()?;
+
         let client: Client = self.pool
             .get()
             .await
@@ -55,10 +192,18 @@ impl UserRepository {
                 err
             })?;
 
-        let statement = client.prepare("select * from users").await?;
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![];
+        let conditions = keyset_conditions(&page, &mut params);
+
+        let statement = client
+            .prepare(&format!(
+                "select * from users where true{} order by {} limit {}",
+                conditions, keyset_order(&page), page.limit + 1
+            ))
+            .await?;
 
         let users = client
-            .query(&statement, &[])
+            .query(&statement, &params[..])
             .await
             .map_err(|err| {
                 error!("Error getting users. {}", err; "query" => "users");
@@ -72,7 +217,70 @@ impl UserRepository {
                 err
             })?;
 
-        Ok(users)
+        let count_statement = client.prepare("select count(*) from users").await?;
+        let total_count: i64 = client.query_one(&count_statement, &[]).await?.get(0);
+
+        Ok(Connection::from_rows(users, &page, total_count))
+    }
+
+    /// Logins containing `@` are emails, usernames can't contain it
+    /// Only one column is checked, so a username equal to another user's email can't match both
+    pub async fn get_by_login(&self, username_or_email: &str) -> Result<User, AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get_by_login");
+                err
+            })?;
+
+        let statement = if username_or_email.contains('@') {
+            client.prepare("select * from users where email = $1").await?
+        } else {
+            client.prepare("select * from users where username = $1").await?
+        };
+
+        client
+            .query(&statement, &[&username_or_email])
+            .await
+            .map_err(|err| {
+                error!("Error getting users. {}", err; "query" => "get_by_login");
+                err
+            })?
+            .iter()
+            .map(|row| User::from_row_ref(row))
+            .collect::<Result<Vec<User>, _>>()?
+            .pop()
+            .ok_or(AppError {
+                cause: None,
+                message: None,
+                error_type: AppErrorType::NotFoundError,
+                field: None
+            })
+    }
+
+    /// Finds the user by username or email and checks the password against the stored hash
+    pub async fn authenticate(&self, username_or_email: String, password: String, hashing: Arc<HashingService>) -> Result<User, AppError> {
+        let invalid_credentials = AppError {
+            message: Some("Invalid username, email or password.".to_string()),
+            cause: None,
+            error_type: AppErrorType::InvalidField,
+            field: None
+        };
+
+        let user = match self.get_by_login(&username_or_email).await {
+            Ok(user) => user,
+            Err(AppError { error_type: AppErrorType::NotFoundError, .. }) => {
+                hashing.verify_dummy(password).await?;
+                return Err(invalid_credentials);
+            },
+            Err(err) => return Err(err)
+        };
+
+        match hashing.verify(password, user.password.clone()).await? {
+            true => Ok(user),
+            false => Err(invalid_credentials)
+        }
     }
 
     pub async fn create(&self, input: CreateUser, hashing: Arc<HashingService>) -> Result<User, AppError> {
@@ -104,9 +312,15 @@ impl UserRepository {
 
                 match unique_error {
                     Some(true) => AppError {
-                            cause: Some(err.to_string()),
                             message: Some("Username or email address already exists.".to_string()),
-                            error_type: AppErrorType::InvalidField
+                            error_type: AppErrorType::InvalidField,
+                            field: err.as_db_error()
+                                .and_then(|db_error| db_error.constraint())
+                                .map(|constraint| match constraint {
+                                    "users_email_key" => "email",
+                                    _ => "username"
+                                }.to_string()),
+                            cause: Some(err.to_string())
                         },
                     _ => AppError::from(err)
                 }
@@ -119,8 +333,74 @@ impl UserRepository {
                 message: Some("Error creating User.".to_string()),
                 cause: Some("Unknown error.".to_string()),
                 error_type: AppErrorType::DbError,
+                field: None
             })?;
 
         Ok(user)
     }
-}
\ No newline at end of file
+}
+
+impl UserBatcher {
+    pub async fn get_users_by_ids(&self, hashmap: &mut HashMap<Uuid, User>, ids: Vec<Uuid>) -> Result<(), AppError> {
+        let client: Client = self.pool
+            .get()
+            .await
+            .map_err(|err| {
+                error!("Error getting client {}", err; "query" => "get_users_by_ids");
+                err
+            })?;
+
+        let statement = client.prepare("select * from users where id = ANY($1)").await?;
+
+        client
+            .query(&statement, &[&ids])
+            .await
+            .map_err(|err| {
+                error!("Error getting users. {}", err; "query" => "get_users_by_ids");
+                err
+            })?
+            .iter()
+            .map(|row| User::from_row_ref(row))
+            .collect::<Result<Vec<User>, _>>()
+            .map_err(|err| {
+                error!("Error getting parsing users. {}", err; "query" => "get_users_by_ids");
+                err
+            })?
+            .into_iter()
+            .for_each(|user| {
+                hashmap.insert(user.id, user);
+            });
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BatchFn<Uuid, User> for UserBatcher {
+    type Error = AppError;
+
+    async fn load(&self, keys: &[Uuid]) -> HashMap<Uuid, Result<User, AppError>> {
+
+        info!("Loading batch {:?}", keys);
+
+        let mut users_map = HashMap::new();
+
+        let result: Result<(), AppError> = self.get_users_by_ids(&mut users_map, keys.into()).await;
+
+        keys
+            .iter()
+            .map(move |id| {
+                let entry = result
+                    .clone()
+                    .and_then(|_| users_map.get(id).cloned().ok_or(AppError {
+                        cause: None,
+                        message: Some(format!("User {} not found.", id)),
+                        error_type: AppErrorType::NotFoundError,
+                        field: None
+                    }));
+
+                (id.clone(), entry)
+            })
+            .collect::<HashMap<_, _>>()
+    }
+}
@@ -4,8 +4,20 @@ table! {
         author_id -> Uuid,
         post_id -> Uuid,
         body -> Text,
-        created_at -> Timestamp,
-        updated_at -> Timestamp,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    one_time_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        purpose -> Token_purpose,
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -17,8 +29,26 @@ table! {
         title -> Varchar,
         description -> Varchar,
         body -> Text,
-        created_at -> Timestamp,
-        updated_at -> Timestamp,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        status -> Post_status,
+        published_at -> Nullable<Timestamptz>,
+        search -> Tsvector,
+    }
+}
+
+table! {
+    posts_tags (post_id, tag_id) {
+        post_id -> Uuid,
+        tag_id -> Uuid,
+    }
+}
+
+table! {
+    tags (id) {
+        id -> Uuid,
+        name -> Varchar,
+        created_at -> Timestamptz,
     }
 }
 
@@ -30,17 +60,26 @@ table! {
         password -> Varchar,
         bio -> Nullable<Varchar>,
         image -> Nullable<Varchar>,
-        created_at -> Timestamp,
-        updated_at -> Timestamp,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        password_changed_at -> Nullable<Timestamptz>,
+        email_verified_at -> Nullable<Timestamptz>,
+        role -> User_role,
     }
 }
 
 joinable!(comments -> posts (post_id));
 joinable!(comments -> users (author_id));
+joinable!(one_time_tokens -> users (user_id));
 joinable!(posts -> users (author_id));
+joinable!(posts_tags -> posts (post_id));
+joinable!(posts_tags -> tags (tag_id));
 
 allow_tables_to_appear_in_same_query!(
     comments,
+    one_time_tokens,
     posts,
+    posts_tags,
+    tags,
     users,
 );